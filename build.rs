@@ -1,5 +1,5 @@
 use bootloader_boot_config::{BootConfig, LevelFilter};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn main() {
     // set by cargo, build scripts should use this directory for output files
@@ -8,18 +8,178 @@ fn main() {
     // https://doc.rust-lang.org/nightly/cargo/reference/unstable.html#artifact-dependencies
     let kernel = PathBuf::from(std::env::var_os("CARGO_BIN_FILE_KERNEL_kernel").unwrap());
 
+    // embed the kernel symbol table, used to symbolize addresses in exceptions
+    let kernel_path = out_dir.join("kernel");
+    embed_symbols(&kernel, &kernel_path);
+
     let mut config = BootConfig::default();
     config.log_level = LevelFilter::Warn;
 
     // create a UEFI disk image
     let uefi_path = out_dir.join("uefi.img");
-    bootloader::UefiBoot::new(&kernel)
+    bootloader::UefiBoot::new(&kernel_path)
         .set_boot_config(&config)
         .create_disk_image(&uefi_path)
         .unwrap();
 
     // pass the disk image paths as env variables to the `main.rs`
     println!("cargo:rustc-env=UEFI_PATH={}", uefi_path.display());
-    println!("cargo:warning=binary path: {}", kernel.display());
+    println!("cargo:warning=binary path: {}", kernel_path.display());
     println!("cargo:warning=image path: {}", uefi_path.display());
 }
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// Kernel symbols
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Name of the kernel section reserved for the symbol table, see `kernel/src/symbols.rs`.
+const SYMBOLS_SECTION: &str = ".ksyms";
+/// Size of the section header written by the kernel: magic, link address and symbols count.
+const SYMBOLS_HEADER_SIZE: usize = 24;
+const SYMBOLS_MAGIC: &[u8; 8] = b"KSYMTAB\0";
+const SYMBOL_ENTRY_SIZE: usize = 24;
+
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+
+struct Section {
+    name: u32,
+    kind: u32,
+    addr: u64,
+    offset: usize,
+    size: usize,
+    link: usize,
+}
+
+struct Symbol {
+    addr: u64,
+    size: u64,
+    name: String,
+}
+
+/// Copies the kernel ELF to `out` with function symbols written to the `.ksyms` section.
+///
+/// Table layout after the header: sorted `(address, size, name offset, name length)` entries
+/// followed by demangled names.
+fn embed_symbols(kernel: &Path, out: &Path) {
+    let mut elf = std::fs::read(kernel).expect("failed to read kernel");
+
+    let sections = parse_sections(&elf);
+    let section_name = |section: &Section| {
+        let strtab = &sections[usize::from(read_u16(&elf, 0x3E))];
+        read_str(&elf, strtab.offset + section.name as usize)
+    };
+
+    let table_section = sections
+        .iter()
+        .find(|s| section_name(s) == SYMBOLS_SECTION)
+        .expect("kernel has no symbols section");
+
+    let mut symbols = Vec::new();
+
+    for symtab in sections.iter().filter(|s| s.kind == SHT_SYMTAB) {
+        let strtab = &sections[symtab.link];
+
+        for entry in elf[symtab.offset..symtab.offset + symtab.size].chunks_exact(24) {
+            let addr = read_u64(entry, 8);
+
+            if entry[4] & 0xF != STT_FUNC || addr == 0 {
+                continue;
+            }
+
+            let name = read_str(&elf, strtab.offset + read_u32(entry, 0) as usize);
+
+            symbols.push(Symbol {
+                addr,
+                size: read_u64(entry, 16),
+                name: format!("{:#}", rustc_demangle::demangle(name)),
+            });
+        }
+    }
+
+    symbols.sort_by_key(|s| s.addr);
+    symbols.dedup_by_key(|s| s.addr);
+
+    let capacity = table_section.size - SYMBOLS_HEADER_SIZE;
+    let mut entries = Vec::new();
+    let mut names = Vec::new();
+    let mut count = 0u64;
+
+    for symbol in &symbols {
+        let len = (count as usize + 1) * SYMBOL_ENTRY_SIZE + names.len() + symbol.name.len();
+
+        if len > capacity {
+            println!(
+                "cargo:warning=kernel symbols section is full, {} symbols skipped",
+                symbols.len() - count as usize
+            );
+            break;
+        }
+
+        entries.extend_from_slice(&symbol.addr.to_le_bytes());
+        entries.extend_from_slice(&symbol.size.to_le_bytes());
+        // Names offsets are relative to the end of entries.
+        entries.extend_from_slice(&(names.len() as u32).to_le_bytes());
+        entries.extend_from_slice(&(symbol.name.len() as u32).to_le_bytes());
+        names.extend_from_slice(symbol.name.as_bytes());
+
+        count += 1;
+    }
+
+    let start = table_section.offset;
+    assert_eq!(
+        &elf[start..start + 8],
+        SYMBOLS_MAGIC,
+        "invalid symbols section"
+    );
+
+    elf[start + 8..start + 16].copy_from_slice(&table_section.addr.to_le_bytes());
+    elf[start + 16..start + 24].copy_from_slice(&count.to_le_bytes());
+
+    let data = start + SYMBOLS_HEADER_SIZE;
+    elf[data..data + entries.len()].copy_from_slice(&entries);
+    elf[data + entries.len()..data + entries.len() + names.len()].copy_from_slice(&names);
+
+    std::fs::write(out, elf).expect("failed to write kernel");
+}
+
+fn parse_sections(elf: &[u8]) -> Vec<Section> {
+    assert_eq!(&elf[..4], b"\x7fELF", "kernel is not an ELF file");
+
+    let offset = read_u64(elf, 0x28) as usize;
+    let entry_size = usize::from(read_u16(elf, 0x3A));
+    let count = usize::from(read_u16(elf, 0x3C));
+
+    (0..count)
+        .map(|idx| {
+            let header = &elf[offset + idx * entry_size..];
+
+            Section {
+                name: read_u32(header, 0),
+                kind: read_u32(header, 4),
+                addr: read_u64(header, 16),
+                offset: read_u64(header, 24) as usize,
+                size: read_u64(header, 32) as usize,
+                link: read_u32(header, 40) as usize,
+            }
+        })
+        .collect()
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_str(buf: &[u8], offset: usize) -> &str {
+    let len = buf[offset..].iter().position(|b| *b == 0).unwrap_or(0);
+
+    std::str::from_utf8(&buf[offset..offset + len]).unwrap_or("")
+}
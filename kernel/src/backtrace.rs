@@ -0,0 +1,134 @@
+//! Kernel stack walker, the kernel is built with frame pointers.
+
+use core::fmt;
+
+use x86_64::VirtAddr;
+
+use crate::memory;
+use crate::symbols;
+
+/// Limits the output on deep or looped stacks.
+const MAX_FRAMES: usize = 32;
+
+/// Chain of return addresses starting from the frame pointer.
+#[derive(Debug, Copy, Clone)]
+pub struct Backtrace {
+    rip: Option<u64>,
+    rbp: u64,
+}
+
+impl Backtrace {
+    /// Captures the backtrace of the caller.
+    #[inline(always)]
+    pub fn current() -> Self {
+        let rbp: u64;
+
+        unsafe {
+            core::arch::asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags));
+        }
+
+        Self { rip: None, rbp }
+    }
+
+    /// Backtrace of the interrupted code, `rip` is printed as the first frame.
+    pub fn from_frame(rip: u64, rbp: u64) -> Self {
+        Self {
+            rip: Some(rip),
+            rbp,
+        }
+    }
+}
+
+impl Backtrace {
+    /// Returns return addresses from the innermost frame, limited to [`MAX_FRAMES`].
+    pub fn frames(&self) -> Frames {
+        Frames {
+            rip: self.rip,
+            rbp: self.rbp,
+            count: 0,
+        }
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Backtrace:")?;
+
+        let mut frames = self.frames();
+
+        for (idx, addr) in frames.by_ref().enumerate() {
+            writeln!(f, "  #{idx:<2} {}", symbols::Address(addr))?;
+        }
+
+        if frames.is_truncated() {
+            writeln!(f, "  ... (truncated)")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Iterator over return addresses of [`Backtrace`].
+pub struct Frames {
+    rip: Option<u64>,
+    rbp: u64,
+    count: usize,
+}
+
+impl Frames {
+    /// Returns `true` if the walk stopped on [`MAX_FRAMES`] limit, not on the end of the stack.
+    pub fn is_truncated(&self) -> bool {
+        self.count == MAX_FRAMES && is_valid_frame(self.rbp)
+    }
+}
+
+impl Iterator for Frames {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.count == MAX_FRAMES {
+            return None;
+        }
+
+        if let Some(rip) = self.rip.take() {
+            self.count += 1;
+            return Some(rip);
+        }
+
+        // Frame layout: saved rbp of the caller followed by the return address.
+        if !is_valid_frame(self.rbp) {
+            return None;
+        }
+
+        let (next, ret) = unsafe {
+            let frame = self.rbp as *const u64;
+            (frame.read(), frame.add(1).read())
+        };
+
+        if ret == 0 {
+            return None;
+        }
+
+        // The stack grows down, so caller frames are always above, otherwise it's corrupted.
+        self.rbp = if next > self.rbp { next } else { 0 };
+        self.count += 1;
+
+        Some(ret)
+    }
+}
+
+fn is_valid_frame(rbp: u64) -> bool {
+    if rbp == 0 || rbp % 8 != 0 {
+        return false;
+    }
+
+    let Some(end) = rbp.checked_add(15) else {
+        return false;
+    };
+
+    let (Ok(start), Ok(end)) = (VirtAddr::try_new(rbp), VirtAddr::try_new(end)) else {
+        return false;
+    };
+
+    memory::is_mapped(start) && memory::is_mapped(end)
+}
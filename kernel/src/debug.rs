@@ -8,20 +8,12 @@ pub fn write_log(record: &log::Record) {
 }
 
 fn write_serial(record: &log::Record) {
-    let color = match record.level() {
-        log::Level::Error => "\x1b[0031m",
-        log::Level::Warn => "\x1b[0033m",
-        log::Level::Info => "\x1b[0032m",
-        log::Level::Debug => "\x1b[0034m",
-        log::Level::Trace => "\x1b[0035m",
-    };
-
     let mut writer = super::devices::serial::COM1.lock();
 
     let _ = writeln!(
         writer,
         "{}[{}]\x1b[0m\t {}",
-        color,
+        level_color(record.level()),
         record.level(),
         record.args()
     );
@@ -32,9 +24,21 @@ fn write_display(record: &log::Record) {
 
     let _ = writeln!(
         display,
-        "[{} {}] {}",
+        "{}[{} {}]\x1b[0m {}",
+        level_color(record.level()),
         record.target(),
         record.level(),
         record.args()
     );
 }
+
+/// Returns the SGR sequence coloring the level, understood by both serial terminals and the display.
+fn level_color(level: log::Level) -> &'static str {
+    match level {
+        log::Level::Error => "\x1b[0031m",
+        log::Level::Warn => "\x1b[0033m",
+        log::Level::Info => "\x1b[0032m",
+        log::Level::Debug => "\x1b[0034m",
+        log::Level::Trace => "\x1b[0035m",
+    }
+}
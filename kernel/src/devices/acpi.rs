@@ -2,8 +2,9 @@ use alloc::vec::Vec;
 use core::ptr::NonNull;
 
 use acpi::fadt::Fadt;
+use acpi::hpet::HpetInfo;
 use acpi::platform::interrupt::Apic;
-use acpi::platform::Processor;
+use acpi::platform::{PmTimer, Processor};
 use acpi::sdt::Signature;
 use acpi::{AcpiTables, InterruptModel, PhysicalMapping};
 use spin::RwLock;
@@ -16,6 +17,8 @@ pub struct AcpiInfo {
     pub boot_processor: Option<Processor>,
     pub ap_processors: Vec<Processor>,
     pub century_reg: Option<u8>,
+    pub hpet: Option<HpetInfo>,
+    pub pm_timer: Option<PmTimer>,
 }
 
 impl AcpiInfo {
@@ -25,6 +28,8 @@ impl AcpiInfo {
             boot_processor: None,
             ap_processors: Vec::new(),
             century_reg: None,
+            hpet: None,
+            pm_timer: None,
         }
     }
 
@@ -45,11 +50,15 @@ impl AcpiInfo {
                 self.boot_processor.replace(pi.boot_processor);
                 self.ap_processors = pi.application_processors;
             }
+
+            self.pm_timer = info.pm_timer;
         }
 
         if let Ok(Some(fadt)) = unsafe { tables.get_sdt::<Fadt>(Signature::FADT) } {
             self.century_reg.replace(fadt.century);
         }
+
+        self.hpet = HpetInfo::new(&tables).ok();
     }
 }
 
@@ -1,22 +1,121 @@
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 
 use acpi::platform::{Processor, ProcessorState};
 use raw_cpuid::CpuId;
+use x86_64::instructions::port::PortWriteOnly;
+use x86_64::instructions::tables::lidt;
 use x86_64::registers::control::Cr3;
+use x86_64::registers::model_specific::FsBase;
 use x86_64::structures::paging::{PageTableFlags, PhysFrame};
+use x86_64::structures::DescriptorTablePointer;
 use x86_64::{PhysAddr, VirtAddr};
 
 use crate::ap_entry;
-use crate::devices::local_apic;
+use crate::devices::{local_apic, tsc};
 use crate::memory::{KERNEL_FRAME_ALLOCATOR, KERNEL_PAGE_MAPPER};
 use crate::prelude::*;
+use crate::time;
 
 static AP_READY: AtomicBool = AtomicBool::new(false);
+static ONLINE_CPUS: AtomicU64 = AtomicU64::new(0);
+
+/// Local APIC ids by CPU id, the BSP is CPU 0 whatever its APIC id is.
+static APIC_IDS: [AtomicU32; KERNEL_MAX_CPUS] = [const { AtomicU32::new(0) }; KERNEL_MAX_CPUS];
+
+#[thread_local]
+static mut CPU_ID: u64 = 0;
 
 pub fn set_ap_is_ready() {
     AP_READY.store(true, Ordering::SeqCst);
 }
 
+/// Sets the id of the current CPU and marks it online, must be called after TLS initialized.
+pub fn init_current(cpu_id: u64) {
+    unsafe { CPU_ID = cpu_id };
+
+    if let Some(apic_id) = APIC_IDS.get(cpu_id as usize) {
+        apic_id.store(current_apic_id(), Ordering::SeqCst);
+    }
+
+    if cpu_id < u64::BITS.into() {
+        ONLINE_CPUS.fetch_or(1 << cpu_id, Ordering::SeqCst);
+    }
+}
+
+/// Returns the id of the current CPU.
+#[inline]
+pub fn current_id() -> u64 {
+    unsafe { CPU_ID }
+}
+
+/// Returns the id of the current CPU, `None` before TLS initialized, e.g. on early panic.
+pub fn try_current_id() -> Option<u64> {
+    (FsBase::read().as_u64() != 0).then(current_id)
+}
+
+/// Returns the local APIC id of the online CPU.
+pub fn apic_id(cpu_id: u64) -> Option<u32> {
+    APIC_IDS
+        .get(cpu_id as usize)
+        .map(|apic_id| apic_id.load(Ordering::SeqCst))
+}
+
+/// Returns ids of all online CPUs.
+pub fn online() -> impl Iterator<Item = u64> {
+    let mask = ONLINE_CPUS.load(Ordering::SeqCst);
+
+    (0..u64::from(u64::BITS)).filter(move |cpu_id| mask & (1 << cpu_id) != 0)
+}
+
+/// Resets the machine through the keyboard controller, falls back to a triple fault.
+pub fn reset() -> ! {
+    unsafe {
+        PortWriteOnly::<u8>::new(0x64).write(0xFE);
+
+        // Empty IDT turns the breakpoint into a triple fault.
+        lidt(&DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::zero(),
+        });
+        core::arch::asm!("int3");
+    }
+
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Returns the TSC frequency in Hz reported by CPUID, if any.
+pub fn tsc_frequency() -> Option<u64> {
+    let cpuid = CpuId::new();
+
+    cpuid
+        .get_tsc_info()
+        .and_then(|info| info.tsc_frequency())
+        .or_else(|| {
+            cpuid
+                .get_processor_frequency_info()
+                .map(|info| u64::from(info.processor_base_frequency()) * 1_000_000)
+        })
+        .filter(|hz| *hz >= 1000)
+}
+
+/// Returns the local APIC id of the current CPU, the x2APIC id if the CPU reports one.
+fn current_apic_id() -> u32 {
+    let cpuid = CpuId::new();
+
+    cpuid
+        .get_extended_topology_info()
+        .and_then(|mut levels| levels.next())
+        .map(|level| level.x2apic_id())
+        .or_else(|| {
+            cpuid
+                .get_feature_info()
+                .map(|finfo| u32::from(finfo.initial_local_apic_id()))
+        })
+        .unwrap_or(0)
+}
+
 pub(super) fn has_x2apic() -> bool {
     let cpuid = CpuId::new();
 
@@ -98,10 +197,16 @@ unsafe fn start_core(ap: &Processor, phys_mem_offset: VirtAddr, page_table: Phys
 
     log::trace!(">> Send init ipi");
     local_apic::LOCAL_APIC.send_init_ipi(local_apic_id);
+    time::mdelay(10);
 
+    // The second start-up IPI is ignored if the AP is already running, as in the MP spec.
     log::trace!(">> Send start ipi");
     let ap_segment = (TRAMPOLINE >> 12) & 0xFF;
-    local_apic::LOCAL_APIC.send_start_ipi(ap_segment as u8, local_apic_id);
+
+    for _ in 0..2 {
+        local_apic::LOCAL_APIC.send_start_ipi(ap_segment as u8, local_apic_id);
+        time::udelay(200);
+    }
 
     log::trace!(">> Wait for trampoline ready");
     while ap_ready.read_volatile() == 0 {
@@ -110,6 +215,7 @@ unsafe fn start_core(ap: &Processor, phys_mem_offset: VirtAddr, page_table: Phys
 
     log::trace!(">> Wait rust code ready");
     while !AP_READY.load(Ordering::SeqCst) {
+        tsc::TSC.sync_bsp();
         core::hint::spin_loop();
     }
 }
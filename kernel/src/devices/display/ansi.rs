@@ -0,0 +1,249 @@
+//! Parser of ECMA-48 escape sequences, only control sequences (CSI) are passed on, other
+//! sequences and strings are consumed and ignored.
+
+const ESC: char = '\x1b';
+const BEL: char = '\x07';
+// Cancel the sequence.
+const CAN: char = '\x18';
+const SUB: char = '\x1a';
+
+const MAX_PARAMS: usize = 16;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Action {
+    /// Printable or control character outside of a sequence.
+    Print(char),
+    Csi(Csi),
+    /// Full reset (RIS).
+    Reset,
+}
+
+/// Control sequence, e.g. `ESC [ 1 ; 31 m`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Csi {
+    params: [u16; MAX_PARAMS],
+    len: usize,
+    /// The sequence starts with `?`.
+    pub private: bool,
+    pub action: char,
+}
+
+impl Csi {
+    const fn new() -> Self {
+        Self {
+            params: [0; MAX_PARAMS],
+            len: 0,
+            private: false,
+            action: '\0',
+        }
+    }
+
+    /// Returns the parameters, missing ones are zero.
+    pub fn params(&self) -> &[u16] {
+        &self.params[..self.len]
+    }
+
+    /// Returns the parameter, `default` if it's missing or zero.
+    pub fn param(&self, index: usize, default: u16) -> u16 {
+        match self.params().get(index) {
+            Some(0) | None => default,
+            Some(value) => *value,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum State {
+    Ground,
+    Escape,
+    /// Escape sequence with intermediate bytes, e.g. character set selection.
+    EscapeIntermediate,
+    Csi,
+    /// Control sequence that is not supported, consumed up to its final byte.
+    CsiIgnore,
+    /// Control string, e.g. OSC, terminated by BEL or ST.
+    String,
+}
+
+pub struct Parser {
+    state: State,
+    csi: Csi,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Ground,
+            csi: Csi::new(),
+        }
+    }
+
+    /// Feeds the character, returns the action once it's complete.
+    pub fn advance(&mut self, ch: char) -> Option<Action> {
+        match ch {
+            ESC => {
+                self.state = State::Escape;
+                return None;
+            }
+            CAN | SUB => {
+                self.state = State::Ground;
+                return None;
+            }
+            _ => (),
+        }
+
+        match self.state {
+            State::Ground => Some(Action::Print(ch)),
+            State::Escape => match ch {
+                '[' => {
+                    self.csi = Csi::new();
+                    self.state = State::Csi;
+                    None
+                }
+                ']' | 'P' | 'X' | '^' | '_' => {
+                    self.state = State::String;
+                    None
+                }
+                '\x20'..='\x2f' => {
+                    self.state = State::EscapeIntermediate;
+                    None
+                }
+                'c' => {
+                    self.state = State::Ground;
+                    Some(Action::Reset)
+                }
+                // Controls are executed within sequences.
+                '\0'..='\x1f' => Some(Action::Print(ch)),
+                _ => {
+                    self.state = State::Ground;
+                    None
+                }
+            },
+            State::EscapeIntermediate => match ch {
+                '\0'..='\x1f' => Some(Action::Print(ch)),
+                '\x20'..='\x2f' => None,
+                _ => {
+                    self.state = State::Ground;
+                    None
+                }
+            },
+            State::Csi => self.csi(ch),
+            State::CsiIgnore => match ch {
+                '\0'..='\x1f' => Some(Action::Print(ch)),
+                '\x40'..='\x7e' => {
+                    self.state = State::Ground;
+                    None
+                }
+                _ => None,
+            },
+            State::String => {
+                if ch == BEL {
+                    self.state = State::Ground;
+                }
+
+                None
+            }
+        }
+    }
+
+    fn csi(&mut self, ch: char) -> Option<Action> {
+        let csi = &mut self.csi;
+
+        match ch {
+            '\0'..='\x1f' => return Some(Action::Print(ch)),
+            '0'..='9' => {
+                if csi.len == 0 {
+                    csi.len = 1;
+                }
+
+                let param = &mut csi.params[csi.len - 1];
+                *param = param
+                    .saturating_mul(10)
+                    .saturating_add(ch as u16 - '0' as u16);
+            }
+            // Subparameters, e.g. of truecolor, are handled like parameters.
+            ';' | ':' => {
+                if csi.len == 0 {
+                    csi.len = 1;
+                }
+
+                if csi.len == MAX_PARAMS {
+                    self.state = State::CsiIgnore;
+                } else {
+                    csi.len += 1;
+                }
+            }
+            '?' if csi.len == 0 && !csi.private => csi.private = true,
+            '\x40'..='\x7e' => {
+                csi.action = ch;
+                self.state = State::Ground;
+
+                return Some(Action::Csi(*csi));
+            }
+            // Other private markers and intermediate bytes are not used by supported sequences.
+            _ => self.state = State::CsiIgnore,
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Vec<Action> {
+        let mut parser = Parser::new();
+        input.chars().filter_map(|ch| parser.advance(ch)).collect()
+    }
+
+    fn csi(input: &str) -> Csi {
+        match parse(input).as_slice() {
+            [Action::Csi(csi)] => *csi,
+            actions => panic!("unexpected actions {actions:?}"),
+        }
+    }
+
+    #[test]
+    fn text_passes_through() {
+        assert_eq!(parse("a\n"), [Action::Print('a'), Action::Print('\n')]);
+    }
+
+    #[test]
+    fn control_sequences() {
+        let sgr = csi("\x1b[0031m");
+        assert_eq!((sgr.params(), sgr.action), (&[31][..], 'm'));
+
+        let cup = csi("\x1b[;5H");
+        assert_eq!(cup.params(), [0, 5]);
+        assert_eq!((cup.param(0, 1), cup.param(1, 1)), (1, 5));
+
+        let truecolor = csi("\x1b[38:2:1:2:3m");
+        assert_eq!(truecolor.params(), [38, 2, 1, 2, 3]);
+
+        let cursor = csi("\x1b[?25l");
+        assert!(cursor.private);
+        assert_eq!((cursor.params(), cursor.action), (&[25][..], 'l'));
+
+        assert_eq!(csi("\x1b[K").params(), []);
+    }
+
+    #[test]
+    fn unsupported_sequences_are_consumed() {
+        assert_eq!(
+            parse("\x1b]0;title\x07a\x1b(Bb\x1b[>1mc\x1b[1\x1b[2Jd"),
+            [
+                Action::Print('a'),
+                Action::Print('b'),
+                Action::Print('c'),
+                Action::Csi(csi("\x1b[2J")),
+                Action::Print('d'),
+            ]
+        );
+    }
+
+    #[test]
+    fn reset() {
+        assert_eq!(parse("\x1bc"), [Action::Reset]);
+    }
+}
@@ -0,0 +1,452 @@
+//! PC Screen Font (PSF1 and PSF2) bitmap fonts with Unicode tables.
+
+/// `RobotoMono` 9x16.
+pub static ROBOTO_MONO: &[u8] = include_bytes!("../../../../fonts/roboto-mono-9x16.psf");
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_HEADER_SIZE: usize = 4;
+const PSF1_MODE_512: u8 = 1 << 0;
+const PSF1_MODE_HAS_TABLE: u8 = 1 << 1;
+const PSF1_MODE_HAS_SEQUENCES: u8 = 1 << 2;
+const PSF1_SEQUENCE: u16 = 0xFFFE;
+const PSF1_SEPARATOR: u16 = 0xFFFF;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HEADER_SIZE: usize = 32;
+const PSF2_HAS_TABLE: u32 = 1 << 0;
+const PSF2_SEQUENCE: u8 = 0xFE;
+const PSF2_SEPARATOR: u8 = 0xFF;
+
+const REPLACEMENT: char = '\u{FFFD}';
+const NO_GLYPH: u16 = u16::MAX;
+
+/// Characters above Latin-1 indexed for binary search, more are looked up in the table.
+const MAX_UNICODE: usize = 1024;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FontError {
+    UnknownFormat,
+    InvalidHeader,
+    Truncated,
+}
+
+/// Unicode table mapping glyphs to the characters they show.
+#[derive(Debug, Copy, Clone)]
+enum Table {
+    None,
+    Psf1(&'static [u8]),
+    Psf2(&'static [u8]),
+}
+
+impl Table {
+    /// Calls `f` with glyphs and their characters until it returns `true`, returns that glyph.
+    ///
+    /// Sequences of combining characters are skipped.
+    fn find(self, count: usize, mut f: impl FnMut(usize, char) -> bool) -> Option<usize> {
+        match self {
+            Self::None => None,
+            Self::Psf1(table) => {
+                let (mut glyph, mut sequence) = (0, false);
+
+                let (values, _) = table.as_chunks::<2>();
+
+                for value in values {
+                    match u16::from_le_bytes(*value) {
+                        PSF1_SEPARATOR => (glyph, sequence) = (glyph + 1, false),
+                        PSF1_SEQUENCE => sequence = true,
+                        value if !sequence && glyph < count => {
+                            let ch = char::from_u32(u32::from(value));
+
+                            if ch.is_some_and(|ch| f(glyph, ch)) {
+                                return Some(glyph);
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+
+                None
+            }
+            Self::Psf2(table) => {
+                let entries = table.split(|byte| *byte == PSF2_SEPARATOR).take(count);
+
+                for (glyph, entry) in entries.enumerate() {
+                    let singles = entry.split(|byte| *byte == PSF2_SEQUENCE).next();
+                    let singles = singles.and_then(|bytes| core::str::from_utf8(bytes).ok());
+
+                    for ch in singles.unwrap_or_default().chars() {
+                        if f(glyph, ch) {
+                            return Some(glyph);
+                        }
+                    }
+                }
+
+                None
+            }
+        }
+    }
+}
+
+/// Glyphs of characters above Latin-1 sorted by character.
+#[derive(Clone)]
+struct Unicode {
+    entries: [(char, u16); MAX_UNICODE],
+    len: usize,
+    // Some characters didn't fit, they are looked up in the table.
+    truncated: bool,
+}
+
+impl Unicode {
+    const fn new() -> Self {
+        Self {
+            entries: [('\0', 0); MAX_UNICODE],
+            len: 0,
+            truncated: false,
+        }
+    }
+
+    fn push(&mut self, ch: char, glyph: u16) {
+        match self.entries.get_mut(self.len) {
+            Some(entry) => {
+                *entry = (ch, glyph);
+                self.len += 1;
+            }
+            None => self.truncated = true,
+        }
+    }
+
+    /// Sorts the entries, characters shown by several glyphs keep the first one.
+    fn sort(&mut self) {
+        self.entries[..self.len].sort_unstable();
+    }
+
+    fn find(&self, ch: char) -> Option<usize> {
+        let entries = &self.entries[..self.len];
+        let index = entries.partition_point(|(mapped, _)| *mapped < ch);
+
+        entries
+            .get(index)
+            .filter(|(mapped, _)| *mapped == ch)
+            .map(|(_, glyph)| usize::from(*glyph))
+    }
+}
+
+#[derive(Clone)]
+pub struct Font {
+    glyphs: &'static [u8],
+    count: usize,
+    width: usize,
+    height: usize,
+    // Bytes of a glyph row.
+    pitch: usize,
+    table: Table,
+    // Glyphs of U+0000 to U+00FF, looked up once as most of the text is Latin-1.
+    latin1: [u16; 256],
+    unicode: Unicode,
+    fallback: usize,
+}
+
+impl Font {
+    pub fn parse(data: &'static [u8]) -> Result<Self, FontError> {
+        if data.starts_with(&PSF2_MAGIC) {
+            Self::parse_psf2(data)
+        } else if data.starts_with(&PSF1_MAGIC) {
+            Self::parse_psf1(data)
+        } else {
+            Err(FontError::UnknownFormat)
+        }
+    }
+
+    fn parse_psf1(data: &'static [u8]) -> Result<Self, FontError> {
+        let mode = *data.get(2).ok_or(FontError::Truncated)?;
+        let height = usize::from(*data.get(3).ok_or(FontError::Truncated)?);
+
+        let count = if mode & PSF1_MODE_512 == 0 { 256 } else { 512 };
+        let end = PSF1_HEADER_SIZE + count * height;
+
+        if data.len() < end {
+            return Err(FontError::Truncated);
+        }
+
+        let table = if mode & (PSF1_MODE_HAS_TABLE | PSF1_MODE_HAS_SEQUENCES) == 0 {
+            Table::None
+        } else {
+            Table::Psf1(&data[end..])
+        };
+
+        Self::new(&data[PSF1_HEADER_SIZE..end], count, 8, height, table)
+    }
+
+    fn parse_psf2(data: &'static [u8]) -> Result<Self, FontError> {
+        let field = |index: usize| {
+            let offset = PSF2_MAGIC.len() + index * 4;
+            let bytes = data.get(offset..offset + 4).ok_or(FontError::Truncated)?;
+
+            usize::try_from(u32::from_le_bytes(bytes.try_into().unwrap()))
+                .map_err(|_| FontError::InvalidHeader)
+        };
+
+        let header_size = field(1)?;
+        let flags = field(2)?;
+        let count = field(3)?;
+        let glyph_size = field(4)?;
+        let height = field(5)?;
+        let width = field(6)?;
+
+        if header_size < PSF2_HEADER_SIZE || glyph_size != width.div_ceil(8) * height {
+            return Err(FontError::InvalidHeader);
+        }
+
+        let end = count
+            .checked_mul(glyph_size)
+            .and_then(|size| size.checked_add(header_size))
+            .ok_or(FontError::InvalidHeader)?;
+
+        if data.len() < end {
+            return Err(FontError::Truncated);
+        }
+
+        let table = if flags & PSF2_HAS_TABLE as usize == 0 {
+            Table::None
+        } else {
+            Table::Psf2(&data[end..])
+        };
+
+        Self::new(&data[header_size..end], count, width, height, table)
+    }
+
+    fn new(
+        glyphs: &'static [u8],
+        count: usize,
+        width: usize,
+        height: usize,
+        table: Table,
+    ) -> Result<Self, FontError> {
+        if count == 0 || width == 0 || height == 0 {
+            return Err(FontError::InvalidHeader);
+        }
+
+        let mut latin1 = [NO_GLYPH; 256];
+        let mut unicode = Unicode::new();
+
+        match table {
+            Table::None => {
+                for (index, glyph) in latin1.iter_mut().enumerate().take(count) {
+                    *glyph = index as u16;
+                }
+            }
+            table => {
+                table.find(count, |glyph, ch| {
+                    let glyph = u16::try_from(glyph).unwrap_or(NO_GLYPH);
+
+                    match latin1.get_mut(ch as usize) {
+                        Some(slot) if *slot == NO_GLYPH => *slot = glyph,
+                        Some(_) => (),
+                        None => unicode.push(ch, glyph),
+                    }
+
+                    false
+                });
+
+                unicode.sort();
+            }
+        }
+
+        let mut font = Self {
+            glyphs,
+            count,
+            width,
+            height,
+            pitch: width.div_ceil(8),
+            table,
+            latin1,
+            unicode,
+            fallback: 0,
+        };
+
+        font.fallback = font.index(REPLACEMENT).or(font.index('?')).unwrap_or(0);
+
+        Ok(font)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the glyph showing the character, `None` if the font has no one.
+    pub fn index(&self, ch: char) -> Option<usize> {
+        if let Some(glyph) = self.latin1.get(ch as usize) {
+            return (*glyph != NO_GLYPH).then_some(usize::from(*glyph));
+        }
+
+        match self.table {
+            Table::None => Some(ch as usize).filter(|index| *index < self.count),
+            _ if !self.unicode.truncated => self.unicode.find(ch),
+            table => self
+                .unicode
+                .find(ch)
+                .or_else(|| table.find(self.count, |_, mapped| mapped == ch)),
+        }
+    }
+
+    /// Returns the glyph of the character, the fallback glyph if the font has no one.
+    pub fn glyph(&self, ch: char) -> Glyph<'_> {
+        let index = self.index(ch).unwrap_or(self.fallback);
+        let size = self.pitch * self.height;
+
+        Glyph {
+            rows: &self.glyphs[index * size..(index + 1) * size],
+            pitch: self.pitch,
+        }
+    }
+}
+
+pub struct Glyph<'a> {
+    rows: &'a [u8],
+    pitch: usize,
+}
+
+impl Glyph<'_> {
+    /// Returns `true` if the pixel is drawn in the foreground color, rows are left aligned.
+    pub fn is_set(&self, x: usize, y: usize) -> bool {
+        self.rows[y * self.pitch + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leak(data: Vec<u8>) -> &'static [u8] {
+        data.leak()
+    }
+
+    /// PSF1 font of 256 glyphs 8x2, each glyph's rows are its index and its complement.
+    fn psf1(table: &[u16]) -> Vec<u8> {
+        let mut data = vec![0x36, 0x04, PSF1_MODE_HAS_TABLE, 2];
+
+        for glyph in 0..=255u8 {
+            data.extend([glyph, !glyph]);
+        }
+
+        for value in table {
+            data.extend(value.to_le_bytes());
+        }
+
+        data
+    }
+
+    #[test]
+    fn embedded_font() {
+        let font = Font::parse(ROBOTO_MONO).unwrap();
+
+        assert_eq!((font.width(), font.height()), (9, 16));
+        assert_eq!(font.index('A'), Some(0x41));
+        assert_eq!(font.index('é'), Some(0xe9));
+        assert_eq!(font.index('\u{263a}'), None);
+        assert_eq!(font.fallback, font.index(REPLACEMENT).unwrap());
+    }
+
+    #[test]
+    fn psf1_table() {
+        // Glyph 0 shows 'a' and U+263A, glyph 1 has a sequence, glyph 2 shows '?'.
+        let table = [
+            0x61,
+            0x263a,
+            PSF1_SEPARATOR,
+            PSF1_SEQUENCE,
+            0x62,
+            PSF1_SEPARATOR,
+            0x3f,
+            PSF1_SEPARATOR,
+        ];
+        let font = Font::parse(leak(psf1(&table))).unwrap();
+
+        assert_eq!(font.index('a'), Some(0));
+        assert_eq!(font.index('\u{263a}'), Some(0));
+        assert_eq!(font.index('b'), None);
+        assert_eq!(font.fallback, 2);
+
+        let glyph = font.glyph('\u{263a}');
+        assert!(!glyph.is_set(0, 0) && glyph.is_set(0, 1));
+
+        let glyph = font.glyph('z');
+        assert!(glyph.is_set(6, 0) && !glyph.is_set(7, 0));
+    }
+
+    #[test]
+    fn psf2_table() {
+        let mut data = PSF2_MAGIC.to_vec();
+
+        for field in [0u32, 32, PSF2_HAS_TABLE, 2, 2, 1, 12] {
+            data.extend(field.to_le_bytes());
+        }
+        data.extend([0xff, 0xf0, 0x10, 0x00]);
+        data.extend("x\u{263a}".as_bytes());
+        data.push(PSF2_SEQUENCE);
+        data.extend("e\u{301}".as_bytes());
+        data.push(PSF2_SEPARATOR);
+        data.extend("\u{FFFD}".as_bytes());
+        data.push(PSF2_SEPARATOR);
+
+        let font = Font::parse(leak(data)).unwrap();
+
+        assert_eq!(font.index('\u{263a}'), Some(0));
+        assert_eq!(font.index('e'), None);
+        assert_eq!(font.fallback, 1);
+
+        let glyph = font.glyph('x');
+        assert!(glyph.is_set(11, 0));
+
+        let glyph = font.glyph('y');
+        assert!(glyph.is_set(3, 0) && !glyph.is_set(4, 0));
+    }
+
+    #[test]
+    fn unicode_index() {
+        // Glyph 0 shows U+2603 and U+263A, glyph 1 shows U+263A too, glyph 2 shows U+2190.
+        let table = [
+            0x2603,
+            0x263a,
+            PSF1_SEPARATOR,
+            0x263a,
+            PSF1_SEPARATOR,
+            0x2190,
+            PSF1_SEPARATOR,
+        ];
+        let font = Font::parse(leak(psf1(&table))).unwrap();
+
+        assert_eq!(font.unicode.len, 4);
+        assert!(!font.unicode.truncated);
+        assert_eq!(font.index('\u{2190}'), Some(2));
+        assert_eq!(font.index('\u{2603}'), Some(0));
+        assert_eq!(font.index('\u{263a}'), Some(0));
+        assert_eq!(font.index('\u{2604}'), None);
+
+        // Characters beyond the index are found in the table.
+        let mut table: Vec<u16> = (0..=MAX_UNICODE as u16).map(|ch| 0x1000 + ch).collect();
+        table.extend([PSF1_SEPARATOR, 0x2190, PSF1_SEPARATOR]);
+        let font = Font::parse(leak(psf1(&table))).unwrap();
+
+        assert!(font.unicode.truncated);
+        assert_eq!(font.index('\u{1000}'), Some(0));
+        assert_eq!(font.index('\u{1400}'), Some(0));
+        assert_eq!(font.index('\u{2190}'), Some(1));
+    }
+
+    #[test]
+    fn invalid_fonts() {
+        assert_eq!(
+            Font::parse(leak(vec![0; 8])).err(),
+            Some(FontError::UnknownFormat)
+        );
+
+        let mut data = psf1(&[]);
+        data.truncate(100);
+        assert_eq!(Font::parse(leak(data)).err(), Some(FontError::Truncated));
+    }
+}
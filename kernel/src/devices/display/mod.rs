@@ -0,0 +1,922 @@
+use core::ptr;
+
+use bootloader_api::info::{FrameBufferInfo, PixelFormat};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use self::ansi::{Action, Csi, Parser};
+use self::font::Font;
+use crate::input::{Event, KeyCode};
+
+mod ansi;
+pub mod font;
+
+pub static DISPLAY: Mutex<Framebuffer> = Mutex::new(Framebuffer::empty());
+
+/// Framebuffer height per step of the default font scale, so high resolutions get about as many
+/// lines as 1080p.
+const SCALE_HEIGHT: usize = 1080;
+const MAX_SCALE: usize = 4;
+
+/// Widest console, wider framebuffers leave the rest of the screen unused.
+const MAX_COLUMNS: usize = 256;
+/// Lines kept by the console, the screen shows the last ones and the rest is the scrollback.
+const MAX_LINES: usize = 512;
+const TAB_WIDTH: usize = 8;
+
+type Line = [Cell; MAX_COLUMNS];
+
+/// Kept apart from [`DISPLAY`], so it's zero initialized and doesn't take space in the image.
+static mut LINES: [Line; MAX_LINES] = [[Cell::BLANK; MAX_COLUMNS]; MAX_LINES];
+
+/// Scrolls the console by half a screen on Shift+PageUp and Shift+PageDown, scales the font up
+/// and down on Ctrl+Plus and Ctrl+Minus.
+pub fn handle_input(event: &Event) {
+    let Event::Key(key) = event else {
+        return;
+    };
+
+    if !key.pressed {
+        return;
+    }
+
+    let (shift, ctrl) = (key.modifiers.shift(), key.modifiers.ctrl());
+
+    // Interrupt handlers log to the display.
+    without_interrupts(|| {
+        let mut display = DISPLAY.lock();
+        let lines = display.rows() / 2;
+
+        match key.key {
+            KeyCode::PageUp if shift => display.scroll_back(lines),
+            KeyCode::PageDown if shift => display.scroll_forward(lines),
+            KeyCode::Equals | KeyCode::KeypadPlus if ctrl => rescale(&mut display, 1),
+            KeyCode::Minus | KeyCode::KeypadMinus if ctrl => rescale(&mut display, -1),
+            _ => (),
+        }
+    });
+}
+
+fn rescale(display: &mut Framebuffer, step: isize) {
+    let Some(font) = display.font().cloned() else {
+        return;
+    };
+
+    let scale = display
+        .scale()
+        .saturating_add_signed(step)
+        .clamp(1, MAX_SCALE);
+
+    if scale != display.scale() {
+        display.set_font(font, scale);
+    }
+}
+
+pub struct Framebuffer {
+    inner: Option<InnerFramebuffer>,
+}
+
+impl Framebuffer {
+    pub const fn empty() -> Self {
+        Self { inner: None }
+    }
+
+    pub(super) fn init(&mut self, info: FrameBufferInfo, buf: &'static mut [u8]) {
+        buf.fill(0); // clear screen.
+
+        let font = Font::parse(font::ROBOTO_MONO).expect("embedded font should be valid");
+        let scale = (info.height / SCALE_HEIGHT).clamp(1, MAX_SCALE);
+        let (columns, rows) = grid_size(&info, &font, scale);
+
+        // Safety: the framebuffer is initialized once and the lines are accessed only through it.
+        let lines = unsafe { &mut *ptr::addr_of_mut!(LINES) };
+
+        let mut inner = InnerFramebuffer {
+            buf,
+            info,
+            grid: Grid::new(lines, columns, rows),
+            font,
+            scale,
+            parser: Parser::new(),
+            pen: Pen::DEFAULT,
+            cursor_visible: true,
+        };
+
+        inner.draw_cursor(true);
+        self.inner.replace(inner);
+    }
+
+    /// Switches the font, glyphs are scaled up by `scale` for high resolution framebuffers.
+    ///
+    /// Returns `false` if a glyph doesn't fit the screen.
+    pub fn set_font(&mut self, font: Font, scale: usize) -> bool {
+        let Some(inner) = self.inner.as_mut() else {
+            return false;
+        };
+
+        let scale = scale.max(1);
+
+        if font.width() * scale > inner.info.width || font.height() * scale > inner.info.height {
+            return false;
+        }
+
+        let (columns, rows) = grid_size(&inner.info, &font, scale);
+
+        inner.font = font;
+        inner.scale = scale;
+        inner.grid.resize(columns, rows);
+        inner.redraw();
+
+        true
+    }
+
+    pub fn font(&self) -> Option<&Font> {
+        self.inner.as_ref().map(|inner| &inner.font)
+    }
+
+    /// Returns how many times glyphs are scaled up.
+    pub fn scale(&self) -> usize {
+        self.inner.as_ref().map_or(1, |inner| inner.scale)
+    }
+
+    /// Returns the number of text lines on the screen.
+    pub fn rows(&self) -> usize {
+        self.inner.as_ref().map_or(0, |inner| inner.grid.rows)
+    }
+
+    /// Shows lines of the scrollback above the screen, the view returns to the bottom on output.
+    pub fn scroll_back(&mut self, lines: usize) {
+        if let Some(inner) = self.inner.as_mut() {
+            let view = inner
+                .grid
+                .view
+                .saturating_add(lines)
+                .min(inner.grid.history);
+            inner.set_view(view);
+        }
+    }
+
+    pub fn scroll_forward(&mut self, lines: usize) {
+        if let Some(inner) = self.inner.as_mut() {
+            inner.set_view(inner.grid.view.saturating_sub(lines));
+        }
+    }
+}
+
+impl core::fmt::Write for Framebuffer {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        if let Some(inner) = self.inner.as_mut() {
+            inner.set_view(0);
+            inner.draw_cursor(false);
+
+            for c in s.chars() {
+                match inner.parser.advance(c) {
+                    Some(Action::Print(c)) => inner.write_char(c),
+                    Some(Action::Csi(csi)) => inner.csi(&csi),
+                    Some(Action::Reset) => inner.reset(),
+                    None => (),
+                }
+            }
+
+            inner.draw_cursor(true);
+        }
+
+        Ok(())
+    }
+}
+
+pub struct InnerFramebuffer {
+    buf: &'static mut [u8],
+    info: FrameBufferInfo,
+    grid: Grid,
+    font: Font,
+    scale: usize,
+    parser: Parser,
+    pen: Pen,
+    cursor_visible: bool,
+}
+
+impl InnerFramebuffer {
+    fn write_char(&mut self, character: char) {
+        match character {
+            '\n' => self.newline(),
+            '\r' => self.grid.column = 0,
+            '\t' => {
+                let column = (self.grid.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                self.grid.column = column.min(self.grid.columns - 1);
+            }
+            '\x08' => self.grid.column = self.grid.column.saturating_sub(1),
+            // Other controls have no glyphs.
+            '\0'..='\x1f' | '\x7f' => (),
+            character => {
+                // Wrapping is deferred, so a line can be filled up to the last column.
+                if self.grid.column >= self.grid.columns {
+                    self.newline();
+                }
+
+                let (row, column) = (self.grid.row, self.grid.column);
+                let (fg, bg) = self.pen.paints();
+                self.grid.screen_line(row)[column] = Cell {
+                    ch: character,
+                    fg,
+                    bg,
+                };
+                self.draw_cell(row, column, false);
+                self.grid.column += 1;
+            }
+        }
+    }
+
+    fn newline(&mut self) {
+        self.grid.column = 0;
+
+        if self.grid.line_feed() {
+            self.scroll();
+        }
+    }
+
+    /// Executes the control sequence, unsupported ones are ignored.
+    fn csi(&mut self, csi: &Csi) {
+        let (rows, columns) = (self.grid.rows, self.grid.columns);
+        let (row, column) = (self.grid.row, self.grid.column.min(columns - 1));
+        let count = usize::from(csi.param(0, 1));
+
+        match (csi.private, csi.action) {
+            (false, 'm') => self.pen.apply_sgr(csi.params()),
+            (false, 'A') => self.move_cursor(row.saturating_sub(count), column),
+            (false, 'B') => self.move_cursor(row + count, column),
+            (false, 'C') => self.move_cursor(row, column + count),
+            (false, 'D') => self.move_cursor(row, column.saturating_sub(count)),
+            (false, 'E') => self.move_cursor(row + count, 0),
+            (false, 'F') => self.move_cursor(row.saturating_sub(count), 0),
+            (false, 'G') => self.move_cursor(row, count - 1),
+            (false, 'H' | 'f') => {
+                let column = usize::from(csi.param(1, 1));
+                self.move_cursor(count - 1, column - 1);
+            }
+            (false, 'J') => match csi.param(0, 0) {
+                0 => {
+                    self.erase(row, column, columns);
+                    (row + 1..rows).for_each(|row| self.erase(row, 0, columns));
+                }
+                1 => {
+                    (0..row).for_each(|row| self.erase(row, 0, columns));
+                    self.erase(row, 0, column + 1);
+                }
+                2 => (0..rows).for_each(|row| self.erase(row, 0, columns)),
+                3 => {
+                    (0..rows).for_each(|row| self.erase(row, 0, columns));
+                    self.grid.history = 0;
+                }
+                _ => (),
+            },
+            (false, 'K') => match csi.param(0, 0) {
+                0 => self.erase(row, column, columns),
+                1 => self.erase(row, 0, column + 1),
+                2 => self.erase(row, 0, columns),
+                _ => (),
+            },
+            (true, 'h' | 'l') if csi.params() == [CURSOR_MODE] => {
+                self.cursor_visible = csi.action == 'h';
+            }
+            _ => (),
+        }
+    }
+
+    /// Moves the cursor to the cell, clamped to the screen.
+    fn move_cursor(&mut self, row: usize, column: usize) {
+        self.grid.row = row.min(self.grid.rows - 1);
+        self.grid.column = column.min(self.grid.columns - 1);
+    }
+
+    /// Erases columns of the screen row from `start` up to `end`.
+    fn erase(&mut self, row: usize, start: usize, end: usize) {
+        self.grid.screen_line(row)[start..end].fill(Cell::BLANK);
+
+        for column in start..end {
+            self.draw_cell(row, column, false);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.pen = Pen::DEFAULT;
+        self.cursor_visible = true;
+        self.grid.clear();
+        self.clear();
+    }
+
+    /// Moves the screen a line up, the bottom line is blank.
+    fn scroll(&mut self) {
+        let line_bytes = self.cell_height() * self.stride() * self.bpp();
+        let screen_bytes = self.grid.rows * line_bytes;
+
+        self.buf.copy_within(line_bytes..screen_bytes, 0);
+        self.buf[screen_bytes - line_bytes..screen_bytes].fill(0);
+    }
+
+    fn set_view(&mut self, view: usize) {
+        if self.grid.view == view {
+            return;
+        }
+
+        self.grid.view = view;
+        self.redraw();
+    }
+
+    fn redraw(&mut self) {
+        self.clear();
+
+        for row in 0..self.grid.rows {
+            for column in 0..self.grid.columns {
+                if self.grid.visible_line(row)[column] != Cell::BLANK {
+                    self.draw_cell(row, column, false);
+                }
+            }
+        }
+
+        self.draw_cursor(true);
+    }
+
+    /// The cursor is hidden while the scrollback is shown.
+    fn draw_cursor(&mut self, visible: bool) {
+        if self.grid.view == 0 {
+            let column = self.grid.column.min(self.grid.columns - 1);
+            self.draw_cell(self.grid.row, column, visible && self.cursor_visible);
+        }
+    }
+
+    /// Draws the cell of the visible line, inverted for the cursor.
+    fn draw_cell(&mut self, row: usize, column: usize, inverted: bool) {
+        let cell = self.grid.visible_line(row)[column];
+
+        let mut fg = cell.fg.resolve(DEFAULT_FOREGROUND);
+        let mut bg = cell.bg.resolve(DEFAULT_BACKGROUND);
+
+        if inverted {
+            core::mem::swap(&mut fg, &mut bg);
+        }
+
+        let fg = self.color_bytes(fg);
+        let bg = self.color_bytes(bg);
+
+        let (bpp, stride, scale) = (self.bpp(), self.stride(), self.scale);
+        let (width, height) = (self.cell_width(), self.cell_height());
+
+        // Blank cells are drawn with the space glyph.
+        let glyph = self.font.glyph(if cell.ch == '\0' { ' ' } else { cell.ch });
+
+        for y in 0..height {
+            let mut offset = ((row * height + y) * stride + column * width) * bpp;
+
+            for x in 0..width {
+                let color = if glyph.is_set(x / scale, y / scale) {
+                    &fg
+                } else {
+                    &bg
+                };
+
+                self.buf[offset..offset + bpp].copy_from_slice(&color[..bpp]);
+                offset += bpp;
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.buf.fill(0);
+    }
+
+    #[inline]
+    fn bpp(&self) -> usize {
+        self.info.bytes_per_pixel
+    }
+
+    #[inline]
+    fn stride(&self) -> usize {
+        self.info.stride
+    }
+
+    #[inline]
+    fn cell_width(&self) -> usize {
+        self.font.width() * self.scale
+    }
+
+    #[inline]
+    fn cell_height(&self) -> usize {
+        self.font.height() * self.scale
+    }
+
+    #[inline]
+    fn color_bytes(&self, color: Color) -> [u8; 4] {
+        color.to_bytes(self.info.pixel_format)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Cell {
+    ch: char,
+    fg: Paint,
+    bg: Paint,
+}
+
+impl Cell {
+    /// Drawn as the empty glyph with the default colors.
+    const BLANK: Self = Self {
+        ch: '\0',
+        fg: Paint::DEFAULT,
+        bg: Paint::DEFAULT,
+    };
+}
+
+/// Returns columns and rows of the console with the font.
+fn grid_size(info: &FrameBufferInfo, font: &Font, scale: usize) -> (usize, usize) {
+    let columns = (info.width / (font.width() * scale)).clamp(1, MAX_COLUMNS);
+    let rows = (info.height / (font.height() * scale)).clamp(1, MAX_LINES - 1);
+
+    (columns, rows)
+}
+
+/// Character cells of the screen and the scrollback above it, lines are used as a ring.
+struct Grid {
+    lines: &'static mut [Line],
+    columns: usize,
+    rows: usize,
+    // Ring index of the top line of the screen.
+    top: usize,
+    // Lines kept above the screen.
+    history: usize,
+    // Lines the view is scrolled back by.
+    view: usize,
+    // Cursor on the screen, the column equals `columns` while wrapping is pending.
+    row: usize,
+    column: usize,
+}
+
+impl Grid {
+    fn new(lines: &'static mut [Line], columns: usize, rows: usize) -> Self {
+        assert!(
+            rows < lines.len(),
+            "console lines must exceed the screen rows"
+        );
+
+        lines.fill([Cell::BLANK; MAX_COLUMNS]);
+
+        Self {
+            lines,
+            columns,
+            rows,
+            top: 0,
+            history: 0,
+            view: 0,
+            row: 0,
+            column: 0,
+        }
+    }
+
+    /// Blanks the screen and the scrollback, the cursor moves home.
+    fn clear(&mut self) {
+        self.lines.fill([Cell::BLANK; MAX_COLUMNS]);
+        self.history = 0;
+        self.view = 0;
+        self.row = 0;
+        self.column = 0;
+    }
+
+    /// Changes the size of the screen, lines above the cursor move into the scrollback if it
+    /// doesn't fit anymore.
+    fn resize(&mut self, columns: usize, rows: usize) {
+        let len = self.lines.len();
+        let shift = (self.row + 1).saturating_sub(rows);
+        let kept = self.rows - shift;
+
+        self.top = (self.top + shift) % len;
+        self.history += shift;
+        self.row -= shift;
+        self.rows = rows;
+
+        // Lines added at the bottom may hold the oldest scrollback.
+        for row in kept..rows {
+            self.screen_line(row).fill(Cell::BLANK);
+        }
+
+        // Hidden columns are dropped, so they don't show up if the screen widens again.
+        for line in self.lines.iter_mut() {
+            line[columns..].fill(Cell::BLANK);
+        }
+
+        self.columns = columns;
+        self.column = self.column.min(columns);
+        self.history = self.history.min(len - rows);
+        self.view = 0;
+    }
+
+    /// Moves the cursor a line down, returns `true` if the screen scrolled to make room.
+    fn line_feed(&mut self) -> bool {
+        if self.row + 1 < self.rows {
+            self.row += 1;
+            return false;
+        }
+
+        // The oldest line of the scrollback becomes the new bottom line when the ring is full.
+        self.top = (self.top + 1) % self.lines.len();
+        self.history = (self.history + 1).min(self.lines.len() - self.rows);
+        self.screen_line(self.rows - 1).fill(Cell::BLANK);
+
+        true
+    }
+
+    fn screen_line(&mut self, row: usize) -> &mut Line {
+        let index = (self.top + row) % self.lines.len();
+        &mut self.lines[index]
+    }
+
+    /// Returns the line shown on the row, taking the scrollback view into account.
+    fn visible_line(&self, row: usize) -> &Line {
+        let len = self.lines.len();
+        &self.lines[(self.top + len - self.view + row) % len]
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// Colors
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+pub const WHITE: Color = Color::new(0x00ff_ffff);
+pub const BLACK: Color = Color::new(0x0000_0000);
+
+const DEFAULT_FOREGROUND: Color = WHITE;
+const DEFAULT_BACKGROUND: Color = BLACK;
+
+/// `DEC` private mode of the cursor visibility.
+const CURSOR_MODE: u16 = 25;
+
+/// VGA colors, the second half is the bright variant of the first.
+const BASIC_COLORS: [u32; 16] = [
+    0x0000_0000,
+    0x00aa_0000,
+    0x0000_aa00,
+    0x00aa_5500,
+    0x0000_00aa,
+    0x00aa_00aa,
+    0x0000_aaaa,
+    0x00aa_aaaa,
+    0x0055_5555,
+    0x00ff_5555,
+    0x0055_ff55,
+    0x00ff_ff55,
+    0x0055_55ff,
+    0x00ff_55ff,
+    0x0055_ffff,
+    0x00ff_ffff,
+];
+
+/// Levels of the 6x6x6 color cube of the 256 color palette.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Returns the color of the 256 color palette: basic colors, the color cube and a gray ramp.
+fn palette(index: u8) -> Color {
+    match index {
+        0..=15 => Color::new(BASIC_COLORS[usize::from(index)]),
+        16..=231 => {
+            let index = usize::from(index - 16);
+            Color::rgb(
+                CUBE_LEVELS[index / 36],
+                CUBE_LEVELS[index / 6 % 6],
+                CUBE_LEVELS[index % 6],
+            )
+        }
+        _ => {
+            let level = 8 + (index - 232) * 10;
+            Color::rgb(level, level, level)
+        }
+    }
+}
+
+/// Color set by SGR.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Ink {
+    Index(u8),
+    Rgb(Color),
+}
+
+impl Ink {
+    /// Bold brightens the basic colors.
+    fn color(self, bold: bool) -> Color {
+        match self {
+            Self::Index(index) if bold && index < 8 => palette(index + 8),
+            Self::Index(index) => palette(index),
+            Self::Rgb(color) => color,
+        }
+    }
+
+    /// Parses the color following SGR 38 or 48: `5;index` or `2;r;g;b`.
+    #[allow(clippy::cast_possible_truncation)]
+    fn parse(params: &mut impl Iterator<Item = u16>) -> Option<Self> {
+        let mut next = || params.next().map(|value| value.min(0xff) as u8);
+
+        match next()? {
+            5 => next().map(Self::Index),
+            2 => Some(Self::Rgb(Color::rgb(next()?, next()?, next()?))),
+            _ => None,
+        }
+    }
+}
+
+/// Attributes of written characters, `None` colors are the defaults.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Pen {
+    fg: Option<Ink>,
+    bg: Option<Ink>,
+    bold: bool,
+    reverse: bool,
+}
+
+impl Pen {
+    const DEFAULT: Self = Self {
+        fg: None,
+        bg: None,
+        bold: false,
+        reverse: false,
+    };
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn apply_sgr(&mut self, params: &[u16]) {
+        // No parameters is a reset.
+        if params.is_empty() {
+            *self = Self::DEFAULT;
+        }
+
+        let mut params = params.iter().copied();
+
+        while let Some(param) = params.next() {
+            match param {
+                0 => *self = Self::DEFAULT,
+                1 => self.bold = true,
+                22 => self.bold = false,
+                7 => self.reverse = true,
+                27 => self.reverse = false,
+                30..=37 => self.fg = Some(Ink::Index((param - 30) as u8)),
+                38 => self.fg = Ink::parse(&mut params).or(self.fg),
+                39 => self.fg = None,
+                40..=47 => self.bg = Some(Ink::Index((param - 40) as u8)),
+                48 => self.bg = Ink::parse(&mut params).or(self.bg),
+                49 => self.bg = None,
+                90..=97 => self.fg = Some(Ink::Index((param - 90 + 8) as u8)),
+                100..=107 => self.bg = Some(Ink::Index((param - 100 + 8) as u8)),
+                _ => (),
+            }
+        }
+    }
+
+    /// Returns the foreground and background paints of cells.
+    fn paints(&self) -> (Paint, Paint) {
+        let fg = self
+            .fg
+            .map_or(DEFAULT_FOREGROUND, |ink| ink.color(self.bold));
+        let bg = self.bg.map_or(DEFAULT_BACKGROUND, |ink| ink.color(false));
+
+        if self.reverse {
+            (Paint::color(bg), Paint::color(fg))
+        } else {
+            (
+                self.fg.map_or(Paint::DEFAULT, |_| Paint::color(fg)),
+                self.bg.map_or(Paint::DEFAULT, |_| Paint::color(bg)),
+            )
+        }
+    }
+}
+
+/// Color of a cell, zero is the default color, so blank lines are zero initialized.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Paint(u32);
+
+impl Paint {
+    const DEFAULT: Self = Self(0);
+    const SET: u32 = 1 << 24;
+
+    fn color(color: Color) -> Self {
+        Self(Self::SET | color.rgb_value())
+    }
+
+    fn resolve(self, default: Color) -> Color {
+        if self == Self::DEFAULT {
+            default
+        } else {
+            Color::new(self.0 & !Self::SET)
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Color {
+    channel: u8,
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+impl Color {
+    /// Creates new color from u32 representation (hex).
+    #[allow(clippy::cast_possible_truncation)]
+    const fn new(value: u32) -> Self {
+        Self {
+            channel: (value >> 24) as u8,
+            r: (value >> 16) as u8,
+            g: (value >> 8) as u8,
+            b: value as u8,
+        }
+    }
+
+    const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self {
+            channel: 0,
+            r,
+            g,
+            b,
+        }
+    }
+
+    /// Returns the u32 representation without the channel.
+    fn rgb_value(self) -> u32 {
+        u32::from(self.r) << 16 | u32::from(self.g) << 8 | u32::from(self.b)
+    }
+
+    /// Converts `Color` to formatted bytes array.
+    fn to_bytes(self, format: PixelFormat) -> [u8; 4] {
+        match format {
+            PixelFormat::Rgb => [self.r, self.g, self.b, self.channel],
+            PixelFormat::Bgr => [self.b, self.g, self.r, self.channel],
+            PixelFormat::U8 => [self.gray(), self.gray(), self.gray(), 0],
+            format => unimplemented!("unimplemented pixel format {format:?}"),
+        }
+    }
+
+    /// Returns a new color with the given intensity.
+    #[allow(dead_code, reason = "bitmap fonts have no antialiasing to blend")]
+    fn intensity(mut self, value: u8) -> Self {
+        #[allow(clippy::cast_possible_truncation)]
+        fn calculate(value: u8, intensity: u8) -> u8 {
+            ((u16::from(value) * u16::from(intensity)) / u16::from(u8::MAX)) as u8
+        }
+
+        self.r = calculate(self.r, value);
+        self.g = calculate(self.g, value);
+        self.b = calculate(self.b, value);
+
+        self
+    }
+
+    #[inline]
+    fn gray(self) -> u8 {
+        self.r / 3 + self.b / 3 + self.g / 3
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{palette, Cell, Color, Grid, Ink, Line, Paint, Pen, PixelFormat, MAX_COLUMNS};
+
+    fn grid(lines: usize, rows: usize) -> Grid {
+        let lines = vec![[Cell::BLANK; MAX_COLUMNS]; lines].leak();
+        Grid::new(lines, 4, rows)
+    }
+
+    fn put(grid: &mut Grid, ch: char) {
+        let row = grid.row;
+        grid.screen_line(row)[0] = Cell { ch, ..Cell::BLANK };
+    }
+
+    fn first_char(line: &Line) -> char {
+        line[0].ch
+    }
+
+    #[test]
+    fn grid_scrolls_into_history() {
+        let mut grid = grid(4, 2);
+
+        put(&mut grid, 'a');
+        assert!(!grid.line_feed());
+        put(&mut grid, 'b');
+        assert!(grid.line_feed());
+        put(&mut grid, 'c');
+
+        assert_eq!(grid.history, 1);
+        assert_eq!(first_char(grid.visible_line(0)), 'b');
+        assert_eq!(first_char(grid.visible_line(1)), 'c');
+
+        grid.view = 1;
+        assert_eq!(first_char(grid.visible_line(0)), 'a');
+        assert_eq!(first_char(grid.visible_line(1)), 'b');
+    }
+
+    #[test]
+    fn grid_history_is_bounded() {
+        let mut grid = grid(4, 2);
+
+        for ch in ['a', 'b', 'c', 'd', 'e'] {
+            put(&mut grid, ch);
+            grid.line_feed();
+        }
+
+        // The new bottom line reuses the oldest one and is blank.
+        assert_eq!(grid.history, 2);
+        assert_eq!(first_char(grid.visible_line(0)), 'e');
+        assert_eq!(grid.visible_line(1)[0], Cell::BLANK);
+
+        grid.view = 2;
+        assert_eq!(first_char(grid.visible_line(0)), 'c');
+        assert_eq!(first_char(grid.visible_line(1)), 'd');
+    }
+
+    #[test]
+    fn grid_resize() {
+        let mut grid = grid(6, 3);
+
+        for ch in ['a', 'b', 'c'] {
+            put(&mut grid, ch);
+            grid.line_feed();
+        }
+
+        // The cursor line stays on the screen when it shrinks.
+        put(&mut grid, 'd');
+        grid.resize(4, 2);
+        assert_eq!((grid.row, grid.history), (1, 2));
+        assert_eq!(first_char(grid.visible_line(0)), 'c');
+        assert_eq!(first_char(grid.visible_line(1)), 'd');
+
+        // Lines added at the bottom are blank.
+        grid.resize(4, 5);
+        assert_eq!((grid.row, grid.history), (1, 1));
+        assert_eq!(first_char(grid.visible_line(1)), 'd');
+        assert!((2..5).all(|row| grid.visible_line(row)[0] == Cell::BLANK));
+    }
+
+    #[test]
+    fn color_from() {
+        let cases = [
+            (0x00000000, (0x00, 0x00, 0x00, 0x00)),
+            (0xff000000, (0xff, 0x00, 0x00, 0x00)),
+            (0x00ff0000, (0x00, 0xff, 0x00, 0x00)),
+            (0x0000ff00, (0x00, 0x00, 0xff, 0x00)),
+            (0x000000ff, (0x00, 0x00, 0x00, 0xff)),
+        ];
+
+        for (value, (channel, r, g, b)) in cases {
+            assert_eq!(Color::new(value), Color { channel, r, g, b });
+        }
+    }
+
+    #[test]
+    fn color_to_bytes() {
+        let c = Color::new(0x11223344);
+        assert_eq!(c.to_bytes(PixelFormat::Rgb), [0x22, 0x33, 0x44, 0x11]);
+        assert_eq!(c.to_bytes(PixelFormat::Bgr), [0x44, 0x33, 0x22, 0x11]);
+        assert_eq!(c.to_bytes(PixelFormat::U8), [0x32, 0x32, 0x32, 0x0]);
+    }
+
+    #[test]
+    fn color_intensity() {
+        let c = Color::new(0x007f7f7f);
+        assert_eq!(c.intensity(0xff), Color::new(0x007f7f7f));
+        assert_eq!(c.intensity(0x7f), Color::new(0x003f3f3f));
+        assert_eq!(c.intensity(0x00), Color::new(0x00000000));
+    }
+
+    #[test]
+    fn palette_colors() {
+        assert_eq!(palette(1), Color::new(0x00aa_0000));
+        assert_eq!(palette(16), Color::new(0x0000_0000));
+        assert_eq!(palette(196), Color::new(0x00ff_0000));
+        assert_eq!(palette(231), Color::new(0x00ff_ffff));
+        assert_eq!(palette(232), Color::new(0x0008_0808));
+        assert_eq!(palette(255), Color::new(0x00ee_eeee));
+    }
+
+    #[test]
+    fn sgr() {
+        let mut pen = Pen::DEFAULT;
+
+        pen.apply_sgr(&[1, 31, 48, 2, 1, 2, 3]);
+        assert_eq!(pen.fg, Some(Ink::Index(1)));
+        assert_eq!(pen.bg, Some(Ink::Rgb(Color::rgb(1, 2, 3))));
+        assert_eq!(
+            pen.paints(),
+            (
+                Paint::color(Color::new(0x00ff_5555)),
+                Paint::color(Color::new(0x0001_0203))
+            )
+        );
+
+        pen.apply_sgr(&[39, 38, 5, 196]);
+        assert_eq!(pen.fg, Some(Ink::Index(196)));
+
+        pen.apply_sgr(&[]);
+        assert_eq!(pen, Pen::DEFAULT);
+        assert_eq!(pen.paints(), (Paint::DEFAULT, Paint::DEFAULT));
+
+        pen.apply_sgr(&[7]);
+        assert_eq!(
+            pen.paints(),
+            (Paint::color(super::BLACK), Paint::color(super::WHITE))
+        );
+    }
+}
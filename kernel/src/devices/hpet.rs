@@ -0,0 +1,298 @@
+//! High precision event timer: the main counter is a clock source, comparators are clock event
+//! devices.
+
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use acpi::hpet::HpetInfo;
+use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::devices::{io_apic, msi};
+use crate::interrupts::{irq, vector};
+use crate::memory::KERNEL_PAGE_MAPPER;
+use crate::time::{ClockSource, NSEC_PER_SEC};
+
+pub static HPET: Hpet = Hpet::empty();
+
+const GENERAL_CAPABILITIES: u64 = 0x000;
+const GENERAL_CONFIG: u64 = 0x010;
+const MAIN_COUNTER: u64 = 0x0F0;
+const TIMER_CONFIG: u64 = 0x100;
+const TIMER_COMPARATOR: u64 = 0x108;
+const TIMER_FSB_ROUTE: u64 = 0x110;
+const TIMER_STRIDE: u64 = 0x20;
+
+const CAP_COUNTER_64BIT: u64 = 1 << 13;
+
+const CONFIG_ENABLE: u64 = 1 << 0;
+const CONFIG_LEGACY_ROUTE: u64 = 1 << 1;
+
+const TIMER_INT_ENABLE: u64 = 1 << 2;
+const TIMER_PERIODIC: u64 = 1 << 3;
+const TIMER_PERIODIC_CAP: u64 = 1 << 4;
+const TIMER_64BIT_CAP: u64 = 1 << 5;
+const TIMER_VAL_SET: u64 = 1 << 6;
+const TIMER_32BIT_MODE: u64 = 1 << 8;
+const TIMER_ROUTE_SHIFT: u64 = 9;
+const TIMER_ROUTE_MASK: u64 = 0x1F << TIMER_ROUTE_SHIFT;
+const TIMER_FSB_ENABLE: u64 = 1 << 14;
+const TIMER_FSB_CAP: u64 = 1 << 15;
+
+/// Femtoseconds in a second, the counter period is reported in femtoseconds.
+const FSEC_PER_SEC: u64 = 1_000_000_000_000_000;
+
+pub struct Hpet {
+    // Virtual address of registers, zero if HPET is not present.
+    base: AtomicU64,
+    frequency: AtomicU64,
+    counter_mask: AtomicU64,
+    comparators: AtomicU8,
+}
+
+impl Hpet {
+    const fn empty() -> Self {
+        Self {
+            base: AtomicU64::new(0),
+            frequency: AtomicU64::new(0),
+            counter_mask: AtomicU64::new(0),
+            comparators: AtomicU8::new(0),
+        }
+    }
+
+    /// Maps registers and starts the main counter from zero.
+    pub(super) fn init(&self, phys_mem_offset: VirtAddr, info: &HpetInfo) {
+        let phys_addr = PhysAddr::new(info.base_address as u64);
+        let virt_addr = phys_mem_offset + phys_addr.as_u64();
+
+        unsafe { map_memory(phys_addr, virt_addr) };
+
+        self.base.store(virt_addr.as_u64(), Ordering::SeqCst);
+
+        let caps = unsafe { self.read(GENERAL_CAPABILITIES) };
+        let period = caps >> 32;
+
+        if period == 0 || period > 100_000_000 {
+            log::warn!("HPET: invalid counter period {period} fs");
+            self.base.store(0, Ordering::SeqCst);
+            return;
+        }
+
+        let counter_mask = if caps & CAP_COUNTER_64BIT == 0 {
+            u64::from(u32::MAX)
+        } else {
+            u64::MAX
+        };
+
+        #[allow(clippy::cast_possible_truncation)]
+        let comparators = ((caps >> 8) & 0x1F) as u8 + 1;
+
+        self.frequency
+            .store(FSEC_PER_SEC / period, Ordering::SeqCst);
+        self.counter_mask.store(counter_mask, Ordering::SeqCst);
+        self.comparators.store(comparators, Ordering::SeqCst);
+
+        unsafe {
+            // The counter can be written only while it's halted.
+            let config = self.read(GENERAL_CONFIG) & !(CONFIG_ENABLE | CONFIG_LEGACY_ROUTE);
+            self.write(GENERAL_CONFIG, config);
+
+            for index in 0..comparators {
+                let offset = timer_offset(index, TIMER_CONFIG);
+                self.write(offset, self.read(offset) & !TIMER_INT_ENABLE);
+            }
+
+            self.write(MAIN_COUNTER, 0);
+            self.write(GENERAL_CONFIG, config | CONFIG_ENABLE);
+        }
+
+        log::debug!(
+            "HPET: {} Hz, {} comparators, {}-bit counter",
+            self.frequency(),
+            comparators,
+            counter_mask.count_ones()
+        );
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.base.load(Ordering::Relaxed) != 0
+    }
+
+    /// Returns the number of comparators.
+    #[allow(dead_code, reason = "no clock event device uses HPET comparators yet")]
+    pub fn comparators(&self) -> u8 {
+        self.comparators.load(Ordering::Relaxed)
+    }
+
+    /// Routes interrupts of the comparator to the handler on the CPU, returns the vector.
+    ///
+    /// FSB delivery is used when supported, otherwise the first I/O APIC input allowed for the
+    /// comparator, legacy ISA inputs are used as the last resort.
+    #[allow(dead_code, reason = "no clock event device uses HPET comparators yet")]
+    pub fn enable_comparator(
+        &self,
+        index: u8,
+        cpu_id: u64,
+        name: &'static str,
+        handler: irq::Handler,
+    ) -> Option<u8> {
+        if index >= self.comparators() {
+            return None;
+        }
+
+        let offset = timer_offset(index, TIMER_CONFIG);
+        let config = unsafe { self.read(offset) };
+
+        let vector = vector::allocate(1, name)?;
+        irq::register(vector, handler);
+
+        let route = if config & TIMER_FSB_CAP != 0 {
+            let route = u64::from(msi::message_address(cpu_id)) << 32
+                | u64::from(msi::message_data(vector));
+
+            unsafe { self.write(timer_offset(index, TIMER_FSB_ROUTE), route) };
+
+            TIMER_FSB_ENABLE
+        } else {
+            // Allowed I/O APIC inputs.
+            let inputs = config >> 32;
+
+            let Some(gsi) = (16..32).chain(0..16).find(|gsi| inputs & (1 << gsi) != 0) else {
+                irq::unregister(vector);
+                vector::free(vector, 1);
+                return None;
+            };
+
+            #[allow(clippy::cast_possible_truncation)]
+            let routed = io_apic::IO_APICS.route(gsi as u8, vector, cpu_id as u8);
+
+            if !routed {
+                irq::unregister(vector);
+                vector::free(vector, 1);
+                return None;
+            }
+
+            gsi << TIMER_ROUTE_SHIFT
+        };
+
+        let mut config =
+            (config & !(TIMER_ROUTE_MASK | TIMER_FSB_ENABLE | TIMER_INT_ENABLE)) | route;
+
+        // Comparisons with a 32-bit counter must be 32-bit too.
+        if self.mask() == u64::from(u32::MAX) || config & TIMER_64BIT_CAP == 0 {
+            config |= TIMER_32BIT_MODE;
+        }
+
+        unsafe { self.write(offset, config) };
+
+        log::debug!("HPET: comparator {index} routed to vector {vector}, CPU{cpu_id}");
+
+        Some(vector)
+    }
+
+    /// Raises the comparator interrupt `hz` times per second.
+    ///
+    /// Returns `false` if the comparator doesn't support periodic mode.
+    #[allow(dead_code, reason = "no clock event device uses HPET comparators yet")]
+    pub fn set_periodic(&self, index: u8, hz: u64) -> bool {
+        let offset = timer_offset(index, TIMER_CONFIG);
+        let config = unsafe { self.read(offset) };
+
+        if config & TIMER_PERIODIC_CAP == 0 {
+            return false;
+        }
+
+        let period = (self.frequency() / hz.max(1)).max(1);
+
+        unsafe {
+            self.write(
+                offset,
+                config | TIMER_PERIODIC | TIMER_VAL_SET | TIMER_INT_ENABLE,
+            );
+
+            // With the value set bit the first write sets the comparator, the second the period.
+            let comparator = timer_offset(index, TIMER_COMPARATOR);
+            self.write(comparator, self.read(MAIN_COUNTER).wrapping_add(period));
+            self.write(comparator, period);
+        }
+
+        true
+    }
+
+    /// Raises the comparator interrupt once after `ns` nanoseconds.
+    #[allow(dead_code, reason = "no clock event device uses HPET comparators yet")]
+    pub fn set_oneshot(&self, index: u8, ns: u64) {
+        let offset = timer_offset(index, TIMER_CONFIG);
+        let ticks = u128::from(self.frequency()) * u128::from(ns) / u128::from(NSEC_PER_SEC);
+
+        #[allow(clippy::cast_possible_truncation)]
+        let ticks = (ticks as u64).clamp(1, self.mask());
+
+        unsafe {
+            let config = self.read(offset) & !TIMER_PERIODIC;
+            self.write(offset, config | TIMER_INT_ENABLE);
+
+            let deadline = self.read(MAIN_COUNTER).wrapping_add(ticks) & self.mask();
+            self.write(timer_offset(index, TIMER_COMPARATOR), deadline);
+        }
+    }
+
+    /// Stops interrupts of the comparator.
+    #[allow(dead_code, reason = "no clock event device uses HPET comparators yet")]
+    pub fn stop(&self, index: u8) {
+        let offset = timer_offset(index, TIMER_CONFIG);
+
+        unsafe {
+            let config = self.read(offset) & !(TIMER_INT_ENABLE | TIMER_PERIODIC);
+            self.write(offset, config);
+        }
+    }
+
+    unsafe fn read(&self, offset: u64) -> u64 {
+        ((self.base.load(Ordering::Relaxed) + offset) as *const u64).read_volatile()
+    }
+
+    unsafe fn write(&self, offset: u64, value: u64) {
+        ((self.base.load(Ordering::Relaxed) + offset) as *mut u64).write_volatile(value);
+    }
+}
+
+impl ClockSource for Hpet {
+    fn name(&self) -> &'static str {
+        "HPET"
+    }
+
+    fn frequency(&self) -> u64 {
+        self.frequency.load(Ordering::Relaxed)
+    }
+
+    fn mask(&self) -> u64 {
+        self.counter_mask.load(Ordering::Relaxed)
+    }
+
+    fn read(&self) -> u64 {
+        if !self.is_present() {
+            return 0;
+        }
+
+        unsafe { self.read(MAIN_COUNTER) & self.mask() }
+    }
+}
+
+fn timer_offset(index: u8, register: u64) -> u64 {
+    register + u64::from(index) * TIMER_STRIDE
+}
+
+unsafe fn map_memory(phys_addr: PhysAddr, virt_addr: VirtAddr) {
+    let page = Page::containing_address(virt_addr);
+    let frame = PhysFrame::containing_address(phys_addr);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+
+    let mut mapper = KERNEL_PAGE_MAPPER.lock();
+
+    if mapper.translate(page.start_address()).is_none() {
+        mapper
+            .map_phys(page, frame, flags)
+            .expect("failed to map HPET")
+            .flush();
+    }
+}
@@ -10,6 +10,7 @@ use x2apic::ioapic::{IrqFlags, RedirectionTableEntry};
 use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame};
 use x86_64::{PhysAddr, VirtAddr};
 
+use crate::interrupts::vector;
 use crate::memory::KERNEL_PAGE_MAPPER;
 
 pub static IO_APICS: IoApics = IoApics::empty();
@@ -58,10 +59,12 @@ impl IoApics {
     }
 
     unsafe fn init_override(&self, irq: u8, apic_id: u8, overrides: &[InterruptSourceOverride]) {
-        let Some((gci, trigger_mode, polarity)) = prepare_override(irq, overrides) else { return };
+        let Some((gci, trigger_mode, polarity)) = prepare_override(irq, overrides) else {
+            return;
+        };
 
         let mut entry = RedirectionTableEntry::default();
-        entry.set_vector(32 + irq);
+        entry.set_vector(vector::IRQ_BASE + irq);
         entry.set_dest(apic_id);
         entry.set_flags(irq_frags(trigger_mode, polarity));
 
@@ -70,6 +73,40 @@ impl IoApics {
         }
     }
 
+    /// Returns the GSI routed to the vector, if any.
+    pub fn source_of(&self, vector: u8) -> Option<u8> {
+        let list = unsafe { self.list.get().as_mut()? };
+
+        list.iter_mut().find_map(|io| {
+            let gsi_start = io.gsi_start;
+
+            (gsi_start..=io.gsi_end).find(|gsi| {
+                let entry = unsafe { io.table_entry(gsi - gsi_start) };
+
+                entry.vector() == vector && !entry.flags().contains(IrqFlags::MASKED)
+            })
+        })
+    }
+
+    /// Routes the GSI to the vector on the CPU, edge triggered and active high.
+    ///
+    /// Returns `false` if there is no I/O APIC for the GSI.
+    pub fn route(&self, gsi: u8, vector: u8, apic_id: u8) -> bool {
+        let Some(io) = (unsafe { self.find_io_apic(gsi) }) else {
+            return false;
+        };
+
+        let mut entry = RedirectionTableEntry::default();
+        entry.set_vector(vector);
+        entry.set_dest(apic_id);
+        entry.set_flags(IrqFlags::empty());
+
+        let irq = gsi - io.gsi_start;
+        unsafe { io.set_table_entry(irq, entry) };
+
+        true
+    }
+
     unsafe fn find_io_apic(&self, gci: u8) -> Option<&mut IoApic> {
         self.list
             .get()
@@ -0,0 +1,750 @@
+//! PS/2 keyboard: decodes scancode sets 1 and 2 into key events with the US layout.
+
+use spin::{Mutex, Once};
+
+use crate::devices::ps2::{self, Device};
+use crate::input::{self, DeviceId, DeviceKind, Event, KeyCode, KeyEvent, Modifiers};
+
+pub static KEYBOARD: Mutex<Decoder> = Mutex::new(Decoder::new(ScancodeSet::Set1));
+static LEDS: Mutex<Leds> = Mutex::new(Leds::new());
+static DEVICE: Once<DeviceId> = Once::new();
+
+const SET_LEDS: u8 = 0xED;
+
+const LED_SCROLL_LOCK: u8 = 1 << 0;
+const LED_NUM_LOCK: u8 = 1 << 1;
+const LED_CAPS_LOCK: u8 = 1 << 2;
+
+const EXTENDED: u8 = 0xE0;
+const PAUSE: u8 = 0xE1;
+const SET2_RELEASE: u8 = 0xF0;
+const SET1_RELEASE: u8 = 0x80;
+
+/// Bytes following [`PAUSE`] in the pause sequence.
+const SET1_PAUSE_LEN: u8 = 5;
+const SET2_PAUSE_LEN: u8 = 7;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ScancodeSet {
+    Set1,
+    Set2,
+}
+
+/// Starts decoding the scancode set the keyboard sends and turns the LEDs off, must be called
+/// with interrupts disabled.
+pub(super) fn init(set: ScancodeSet) {
+    *KEYBOARD.lock() = Decoder::new(set);
+    *LEDS.lock() = Leds::new();
+    DEVICE.call_once(|| input::register("PS/2 keyboard", DeviceKind::Keyboard));
+
+    // Lock keys start off, so do the LEDs.
+    let mut controller = ps2::CONTROLLER.lock();
+
+    if !(controller.command(Device::Keyboard, SET_LEDS) && controller.command(Device::Keyboard, 0))
+    {
+        log::warn!("PS/2 keyboard: failed to set LEDs");
+    }
+}
+
+/// Decodes the byte received from the keyboard and queues the key event, must be called from the
+/// interrupt handler.
+pub fn handle_byte(byte: u8) {
+    let Some(device) = DEVICE.get() else {
+        return;
+    };
+
+    let mut keyboard = KEYBOARD.lock();
+    let previous = keyboard.leds();
+
+    let event = keyboard.decode(byte);
+
+    // Acknowledgements are ignored by the decoder and never change the lock keys.
+    let mut leds = LEDS.lock();
+    let next = if keyboard.leds() == previous {
+        leds.respond(byte)
+    } else {
+        leds.update(keyboard.leds())
+    };
+
+    if let Some(next) = next {
+        ps2::CONTROLLER.lock().send(Device::Keyboard, next);
+    }
+
+    if let Some(event) = event {
+        input::push(*device, Event::Key(event));
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum LedState {
+    Idle,
+    /// The command is sent, the LED bits follow its acknowledgement.
+    Command(u8),
+    /// The LED bits are sent.
+    Data(u8),
+}
+
+/// Sends the set LEDs command from the interrupt handler, a byte per acknowledgement.
+struct Leds {
+    state: LedState,
+    // LED bits changed while a command is in progress.
+    queued: Option<u8>,
+}
+
+impl Leds {
+    const fn new() -> Self {
+        Self {
+            state: LedState::Idle,
+            queued: None,
+        }
+    }
+
+    /// Starts setting the LEDs, returns the byte to send.
+    fn update(&mut self, leds: u8) -> Option<u8> {
+        if self.state != LedState::Idle {
+            self.queued = Some(leds);
+            return None;
+        }
+
+        self.state = LedState::Command(leds);
+
+        Some(SET_LEDS)
+    }
+
+    /// Advances on the keyboard response, returns the byte to send.
+    fn respond(&mut self, byte: u8) -> Option<u8> {
+        match (self.state, byte) {
+            (LedState::Command(leds), ps2::ACK) => {
+                self.state = LedState::Data(leds);
+                Some(leds)
+            }
+            (LedState::Data(_), ps2::ACK) => {
+                self.state = LedState::Idle;
+                self.queued.take().and_then(|leds| self.update(leds))
+            }
+            (LedState::Command(_), ps2::RESEND) => Some(SET_LEDS),
+            (LedState::Data(leds), ps2::RESEND) => Some(leds),
+            _ => None,
+        }
+    }
+}
+
+pub struct Decoder {
+    set: ScancodeSet,
+    extended: bool,
+    release: bool,
+    // Bytes of the pause sequence left to skip.
+    pause: u8,
+    modifiers: Modifiers,
+    // Lock keys held down, their typematic repeats don't toggle them.
+    held: Modifiers,
+}
+
+impl Decoder {
+    pub const fn new(set: ScancodeSet) -> Self {
+        Self {
+            set,
+            extended: false,
+            release: false,
+            pause: 0,
+            modifiers: Modifiers::empty(),
+            held: Modifiers::empty(),
+        }
+    }
+
+    #[allow(dead_code, reason = "modifiers are passed with every key event")]
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    /// Returns LED bits of the lock keys state for the set LEDs command.
+    pub fn leds(&self) -> u8 {
+        let mut leds = 0;
+
+        if self.modifiers.intersects(Modifiers::SCROLL_LOCK) {
+            leds |= LED_SCROLL_LOCK;
+        }
+
+        if self.modifiers.intersects(Modifiers::NUM_LOCK) {
+            leds |= LED_NUM_LOCK;
+        }
+
+        if self.modifiers.intersects(Modifiers::CAPS_LOCK) {
+            leds |= LED_CAPS_LOCK;
+        }
+
+        leds
+    }
+
+    /// Feeds the byte, returns the event once a scancode is complete.
+    ///
+    /// Pause has no release code, so only its press is reported.
+    pub fn decode(&mut self, byte: u8) -> Option<KeyEvent> {
+        if self.pause > 0 {
+            self.pause -= 1;
+
+            return (self.pause == 0).then(|| self.event(KeyCode::Pause, true));
+        }
+
+        match byte {
+            // Device responses, not keys.
+            ps2::ACK | ps2::RESEND | 0x00 | 0xEE | 0xFF => return None,
+            EXTENDED => {
+                self.extended = true;
+                return None;
+            }
+            PAUSE => {
+                self.pause = match self.set {
+                    ScancodeSet::Set1 => SET1_PAUSE_LEN,
+                    ScancodeSet::Set2 => SET2_PAUSE_LEN,
+                };
+                return None;
+            }
+            SET2_RELEASE if self.set == ScancodeSet::Set2 => {
+                self.release = true;
+                return None;
+            }
+            _ => (),
+        }
+
+        let extended = core::mem::take(&mut self.extended);
+
+        let (code, pressed) = match self.set {
+            ScancodeSet::Set1 => (byte & !SET1_RELEASE, byte & SET1_RELEASE == 0),
+            ScancodeSet::Set2 => (byte, !core::mem::take(&mut self.release)),
+        };
+
+        let key = match (self.set, extended) {
+            (ScancodeSet::Set1, false) => set1_key(code),
+            (ScancodeSet::Set1, true) => set1_extended_key(code),
+            (ScancodeSet::Set2, false) => set2_key(code),
+            (ScancodeSet::Set2, true) => set2_extended_key(code),
+        }?;
+
+        self.update_modifiers(key, pressed);
+
+        Some(self.event(key, pressed))
+    }
+
+    fn event(&self, key: KeyCode, pressed: bool) -> KeyEvent {
+        KeyEvent {
+            key,
+            pressed,
+            modifiers: self.modifiers,
+            ch: pressed.then(|| us_char(key, self.modifiers)).flatten(),
+        }
+    }
+
+    fn update_modifiers(&mut self, key: KeyCode, pressed: bool) {
+        // Lock keys toggle on the first press, not on repeats.
+        let lock = match key {
+            KeyCode::CapsLock => Some(Modifiers::CAPS_LOCK),
+            KeyCode::NumLock => Some(Modifiers::NUM_LOCK),
+            KeyCode::ScrollLock => Some(Modifiers::SCROLL_LOCK),
+            _ => None,
+        };
+
+        if let Some(lock) = lock {
+            if pressed && !self.held.intersects(lock) {
+                self.modifiers.toggle(lock);
+            }
+
+            self.held.set(lock, pressed);
+
+            return;
+        }
+
+        let modifier = match key {
+            KeyCode::LeftShift => Modifiers::LEFT_SHIFT,
+            KeyCode::RightShift => Modifiers::RIGHT_SHIFT,
+            KeyCode::LeftCtrl => Modifiers::LEFT_CTRL,
+            KeyCode::RightCtrl => Modifiers::RIGHT_CTRL,
+            KeyCode::LeftAlt => Modifiers::LEFT_ALT,
+            KeyCode::RightAlt => Modifiers::RIGHT_ALT,
+            KeyCode::LeftGui => Modifiers::LEFT_GUI,
+            KeyCode::RightGui => Modifiers::RIGHT_GUI,
+            _ => return,
+        };
+
+        self.modifiers.set(modifier, pressed);
+    }
+}
+
+/// Translates the key to a character with the US layout.
+pub fn us_char(key: KeyCode, modifiers: Modifiers) -> Option<char> {
+    let shift = modifiers.shift();
+    let num_lock = modifiers.intersects(Modifiers::NUM_LOCK);
+
+    let pick = |normal: char, shifted: char| if shift { shifted } else { normal };
+
+    let ch = match key {
+        KeyCode::Grave => pick('`', '~'),
+        KeyCode::Digit1 => pick('1', '!'),
+        KeyCode::Digit2 => pick('2', '@'),
+        KeyCode::Digit3 => pick('3', '#'),
+        KeyCode::Digit4 => pick('4', '$'),
+        KeyCode::Digit5 => pick('5', '%'),
+        KeyCode::Digit6 => pick('6', '^'),
+        KeyCode::Digit7 => pick('7', '&'),
+        KeyCode::Digit8 => pick('8', '*'),
+        KeyCode::Digit9 => pick('9', '('),
+        KeyCode::Digit0 => pick('0', ')'),
+        KeyCode::Minus => pick('-', '_'),
+        KeyCode::Equals => pick('=', '+'),
+        KeyCode::LeftBracket => pick('[', '{'),
+        KeyCode::RightBracket => pick(']', '}'),
+        KeyCode::Backslash => pick('\\', '|'),
+        KeyCode::Semicolon => pick(';', ':'),
+        KeyCode::Quote => pick('\'', '"'),
+        KeyCode::Comma => pick(',', '<'),
+        KeyCode::Period => pick('.', '>'),
+        KeyCode::Slash => pick('/', '?'),
+
+        KeyCode::Backspace => '\x08',
+        KeyCode::Tab => '\t',
+        KeyCode::Enter | KeyCode::KeypadEnter => '\n',
+        KeyCode::Space => ' ',
+        KeyCode::Escape => '\x1b',
+
+        KeyCode::KeypadSlash => '/',
+        KeyCode::KeypadStar => '*',
+        KeyCode::KeypadMinus => '-',
+        KeyCode::KeypadPlus => '+',
+        KeyCode::KeypadPeriod if num_lock => '.',
+        KeyCode::Keypad0 if num_lock => '0',
+        KeyCode::Keypad1 if num_lock => '1',
+        KeyCode::Keypad2 if num_lock => '2',
+        KeyCode::Keypad3 if num_lock => '3',
+        KeyCode::Keypad4 if num_lock => '4',
+        KeyCode::Keypad5 if num_lock => '5',
+        KeyCode::Keypad6 if num_lock => '6',
+        KeyCode::Keypad7 if num_lock => '7',
+        KeyCode::Keypad8 if num_lock => '8',
+        KeyCode::Keypad9 if num_lock => '9',
+
+        key => return letter(key, modifiers),
+    };
+
+    Some(ch)
+}
+
+fn letter(key: KeyCode, modifiers: Modifiers) -> Option<char> {
+    let ch = match key {
+        KeyCode::A => 'a',
+        KeyCode::B => 'b',
+        KeyCode::C => 'c',
+        KeyCode::D => 'd',
+        KeyCode::E => 'e',
+        KeyCode::F => 'f',
+        KeyCode::G => 'g',
+        KeyCode::H => 'h',
+        KeyCode::I => 'i',
+        KeyCode::J => 'j',
+        KeyCode::K => 'k',
+        KeyCode::L => 'l',
+        KeyCode::M => 'm',
+        KeyCode::N => 'n',
+        KeyCode::O => 'o',
+        KeyCode::P => 'p',
+        KeyCode::Q => 'q',
+        KeyCode::R => 'r',
+        KeyCode::S => 's',
+        KeyCode::T => 't',
+        KeyCode::U => 'u',
+        KeyCode::V => 'v',
+        KeyCode::W => 'w',
+        KeyCode::X => 'x',
+        KeyCode::Y => 'y',
+        KeyCode::Z => 'z',
+        _ => return None,
+    };
+
+    // Ctrl with a letter gives the control character, e.g. Ctrl+C is ETX.
+    if modifiers.ctrl() {
+        return Some(char::from(ch as u8 & 0x1F));
+    }
+
+    if modifiers.shift() != modifiers.intersects(Modifiers::CAPS_LOCK) {
+        Some(ch.to_ascii_uppercase())
+    } else {
+        Some(ch)
+    }
+}
+
+fn set1_key(code: u8) -> Option<KeyCode> {
+    let key = match code {
+        0x01 => KeyCode::Escape,
+        0x02 => KeyCode::Digit1,
+        0x03 => KeyCode::Digit2,
+        0x04 => KeyCode::Digit3,
+        0x05 => KeyCode::Digit4,
+        0x06 => KeyCode::Digit5,
+        0x07 => KeyCode::Digit6,
+        0x08 => KeyCode::Digit7,
+        0x09 => KeyCode::Digit8,
+        0x0A => KeyCode::Digit9,
+        0x0B => KeyCode::Digit0,
+        0x0C => KeyCode::Minus,
+        0x0D => KeyCode::Equals,
+        0x0E => KeyCode::Backspace,
+        0x0F => KeyCode::Tab,
+        0x10 => KeyCode::Q,
+        0x11 => KeyCode::W,
+        0x12 => KeyCode::E,
+        0x13 => KeyCode::R,
+        0x14 => KeyCode::T,
+        0x15 => KeyCode::Y,
+        0x16 => KeyCode::U,
+        0x17 => KeyCode::I,
+        0x18 => KeyCode::O,
+        0x19 => KeyCode::P,
+        0x1A => KeyCode::LeftBracket,
+        0x1B => KeyCode::RightBracket,
+        0x1C => KeyCode::Enter,
+        0x1D => KeyCode::LeftCtrl,
+        0x1E => KeyCode::A,
+        0x1F => KeyCode::S,
+        0x20 => KeyCode::D,
+        0x21 => KeyCode::F,
+        0x22 => KeyCode::G,
+        0x23 => KeyCode::H,
+        0x24 => KeyCode::J,
+        0x25 => KeyCode::K,
+        0x26 => KeyCode::L,
+        0x27 => KeyCode::Semicolon,
+        0x28 => KeyCode::Quote,
+        0x29 => KeyCode::Grave,
+        0x2A => KeyCode::LeftShift,
+        0x2B => KeyCode::Backslash,
+        0x2C => KeyCode::Z,
+        0x2D => KeyCode::X,
+        0x2E => KeyCode::C,
+        0x2F => KeyCode::V,
+        0x30 => KeyCode::B,
+        0x31 => KeyCode::N,
+        0x32 => KeyCode::M,
+        0x33 => KeyCode::Comma,
+        0x34 => KeyCode::Period,
+        0x35 => KeyCode::Slash,
+        0x36 => KeyCode::RightShift,
+        0x37 => KeyCode::KeypadStar,
+        0x38 => KeyCode::LeftAlt,
+        0x39 => KeyCode::Space,
+        0x3A => KeyCode::CapsLock,
+        0x3B => KeyCode::F1,
+        0x3C => KeyCode::F2,
+        0x3D => KeyCode::F3,
+        0x3E => KeyCode::F4,
+        0x3F => KeyCode::F5,
+        0x40 => KeyCode::F6,
+        0x41 => KeyCode::F7,
+        0x42 => KeyCode::F8,
+        0x43 => KeyCode::F9,
+        0x44 => KeyCode::F10,
+        0x45 => KeyCode::NumLock,
+        0x46 => KeyCode::ScrollLock,
+        0x47 => KeyCode::Keypad7,
+        0x48 => KeyCode::Keypad8,
+        0x49 => KeyCode::Keypad9,
+        0x4A => KeyCode::KeypadMinus,
+        0x4B => KeyCode::Keypad4,
+        0x4C => KeyCode::Keypad5,
+        0x4D => KeyCode::Keypad6,
+        0x4E => KeyCode::KeypadPlus,
+        0x4F => KeyCode::Keypad1,
+        0x50 => KeyCode::Keypad2,
+        0x51 => KeyCode::Keypad3,
+        0x52 => KeyCode::Keypad0,
+        0x53 => KeyCode::KeypadPeriod,
+        0x57 => KeyCode::F11,
+        0x58 => KeyCode::F12,
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+/// Fake shifts around print screen and navigation keys are not mapped, so they are ignored.
+fn set1_extended_key(code: u8) -> Option<KeyCode> {
+    let key = match code {
+        0x1C => KeyCode::KeypadEnter,
+        0x1D => KeyCode::RightCtrl,
+        0x35 => KeyCode::KeypadSlash,
+        0x37 => KeyCode::PrintScreen,
+        0x38 => KeyCode::RightAlt,
+        0x47 => KeyCode::Home,
+        0x48 => KeyCode::Up,
+        0x49 => KeyCode::PageUp,
+        0x4B => KeyCode::Left,
+        0x4D => KeyCode::Right,
+        0x4F => KeyCode::End,
+        0x50 => KeyCode::Down,
+        0x51 => KeyCode::PageDown,
+        0x52 => KeyCode::Insert,
+        0x53 => KeyCode::Delete,
+        0x5B => KeyCode::LeftGui,
+        0x5C => KeyCode::RightGui,
+        0x5D => KeyCode::Menu,
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+fn set2_key(code: u8) -> Option<KeyCode> {
+    let key = match code {
+        0x01 => KeyCode::F9,
+        0x03 => KeyCode::F5,
+        0x04 => KeyCode::F3,
+        0x05 => KeyCode::F1,
+        0x06 => KeyCode::F2,
+        0x07 => KeyCode::F12,
+        0x09 => KeyCode::F10,
+        0x0A => KeyCode::F8,
+        0x0B => KeyCode::F6,
+        0x0C => KeyCode::F4,
+        0x0D => KeyCode::Tab,
+        0x0E => KeyCode::Grave,
+        0x11 => KeyCode::LeftAlt,
+        0x12 => KeyCode::LeftShift,
+        0x14 => KeyCode::LeftCtrl,
+        0x15 => KeyCode::Q,
+        0x16 => KeyCode::Digit1,
+        0x1A => KeyCode::Z,
+        0x1B => KeyCode::S,
+        0x1C => KeyCode::A,
+        0x1D => KeyCode::W,
+        0x1E => KeyCode::Digit2,
+        0x21 => KeyCode::C,
+        0x22 => KeyCode::X,
+        0x23 => KeyCode::D,
+        0x24 => KeyCode::E,
+        0x25 => KeyCode::Digit4,
+        0x26 => KeyCode::Digit3,
+        0x29 => KeyCode::Space,
+        0x2A => KeyCode::V,
+        0x2B => KeyCode::F,
+        0x2C => KeyCode::T,
+        0x2D => KeyCode::R,
+        0x2E => KeyCode::Digit5,
+        0x31 => KeyCode::N,
+        0x32 => KeyCode::B,
+        0x33 => KeyCode::H,
+        0x34 => KeyCode::G,
+        0x35 => KeyCode::Y,
+        0x36 => KeyCode::Digit6,
+        0x3A => KeyCode::M,
+        0x3B => KeyCode::J,
+        0x3C => KeyCode::U,
+        0x3D => KeyCode::Digit7,
+        0x3E => KeyCode::Digit8,
+        0x41 => KeyCode::Comma,
+        0x42 => KeyCode::K,
+        0x43 => KeyCode::I,
+        0x44 => KeyCode::O,
+        0x45 => KeyCode::Digit0,
+        0x46 => KeyCode::Digit9,
+        0x49 => KeyCode::Period,
+        0x4A => KeyCode::Slash,
+        0x4B => KeyCode::L,
+        0x4C => KeyCode::Semicolon,
+        0x4D => KeyCode::P,
+        0x4E => KeyCode::Minus,
+        0x52 => KeyCode::Quote,
+        0x54 => KeyCode::LeftBracket,
+        0x55 => KeyCode::Equals,
+        0x58 => KeyCode::CapsLock,
+        0x59 => KeyCode::RightShift,
+        0x5A => KeyCode::Enter,
+        0x5B => KeyCode::RightBracket,
+        0x5D => KeyCode::Backslash,
+        0x66 => KeyCode::Backspace,
+        0x69 => KeyCode::Keypad1,
+        0x6B => KeyCode::Keypad4,
+        0x6C => KeyCode::Keypad7,
+        0x70 => KeyCode::Keypad0,
+        0x71 => KeyCode::KeypadPeriod,
+        0x72 => KeyCode::Keypad2,
+        0x73 => KeyCode::Keypad5,
+        0x74 => KeyCode::Keypad6,
+        0x75 => KeyCode::Keypad8,
+        0x76 => KeyCode::Escape,
+        0x77 => KeyCode::NumLock,
+        0x78 => KeyCode::F11,
+        0x79 => KeyCode::KeypadPlus,
+        0x7A => KeyCode::Keypad3,
+        0x7B => KeyCode::KeypadMinus,
+        0x7C => KeyCode::KeypadStar,
+        0x7D => KeyCode::Keypad9,
+        0x7E => KeyCode::ScrollLock,
+        0x83 => KeyCode::F7,
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+/// Fake shifts around print screen and navigation keys are not mapped, so they are ignored.
+fn set2_extended_key(code: u8) -> Option<KeyCode> {
+    let key = match code {
+        0x11 => KeyCode::RightAlt,
+        0x14 => KeyCode::RightCtrl,
+        0x1F => KeyCode::LeftGui,
+        0x27 => KeyCode::RightGui,
+        0x2F => KeyCode::Menu,
+        0x4A => KeyCode::KeypadSlash,
+        0x5A => KeyCode::KeypadEnter,
+        0x69 => KeyCode::End,
+        0x6B => KeyCode::Left,
+        0x6C => KeyCode::Home,
+        0x70 => KeyCode::Insert,
+        0x71 => KeyCode::Delete,
+        0x72 => KeyCode::Down,
+        0x74 => KeyCode::Right,
+        0x75 => KeyCode::Up,
+        0x7A => KeyCode::PageDown,
+        0x7C => KeyCode::PrintScreen,
+        0x7D => KeyCode::PageUp,
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(decoder: &mut Decoder, bytes: &[u8]) -> Vec<(KeyCode, bool, Option<char>)> {
+        bytes
+            .iter()
+            .filter_map(|byte| decoder.decode(*byte))
+            .map(|event| (event.key, event.pressed, event.ch))
+            .collect()
+    }
+
+    #[test]
+    fn set1_shifted_letter() {
+        let mut decoder = Decoder::new(ScancodeSet::Set1);
+        let events = decode_all(&mut decoder, &[0x2A, 0x1E, 0x9E, 0xAA, 0x1E]);
+
+        assert_eq!(
+            events,
+            [
+                (KeyCode::LeftShift, true, None),
+                (KeyCode::A, true, Some('A')),
+                (KeyCode::A, false, None),
+                (KeyCode::LeftShift, false, None),
+                (KeyCode::A, true, Some('a')),
+            ]
+        );
+    }
+
+    #[test]
+    fn set2_extended_and_release() {
+        let mut decoder = Decoder::new(ScancodeSet::Set2);
+        let events = decode_all(
+            &mut decoder,
+            &[0xE0, 0x75, 0xE0, 0xF0, 0x75, 0x1C, 0xF0, 0x1C],
+        );
+
+        assert_eq!(
+            events,
+            [
+                (KeyCode::Up, true, None),
+                (KeyCode::Up, false, None),
+                (KeyCode::A, true, Some('a')),
+                (KeyCode::A, false, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn pause_sequences() {
+        let mut decoder = Decoder::new(ScancodeSet::Set1);
+        let events = decode_all(&mut decoder, &[0xE1, 0x1D, 0x45, 0xE1, 0x9D, 0xC5, 0x1E]);
+        assert_eq!(
+            events,
+            [(KeyCode::Pause, true, None), (KeyCode::A, true, Some('a'))]
+        );
+
+        let mut decoder = Decoder::new(ScancodeSet::Set2);
+        let bytes = [0xE1, 0x14, 0x77, 0xE1, 0xF0, 0x14, 0xF0, 0x77, 0x1C];
+        let events = decode_all(&mut decoder, &bytes);
+        assert_eq!(
+            events,
+            [(KeyCode::Pause, true, None), (KeyCode::A, true, Some('a'))]
+        );
+    }
+
+    #[test]
+    fn print_screen_fake_shifts_are_ignored() {
+        let mut decoder = Decoder::new(ScancodeSet::Set1);
+        let events = decode_all(
+            &mut decoder,
+            &[0xE0, 0x2A, 0xE0, 0x37, 0xE0, 0xB7, 0xE0, 0xAA],
+        );
+
+        assert_eq!(
+            events,
+            [
+                (KeyCode::PrintScreen, true, None),
+                (KeyCode::PrintScreen, false, None)
+            ]
+        );
+        assert_eq!(decoder.modifiers(), Modifiers::empty());
+    }
+
+    #[test]
+    fn lock_keys_and_leds() {
+        let mut decoder = Decoder::new(ScancodeSet::Set1);
+
+        decode_all(&mut decoder, &[0x3A, 0xBA, 0x45, 0xC5]);
+        assert_eq!(decoder.leds(), LED_CAPS_LOCK | LED_NUM_LOCK);
+
+        let events = decode_all(&mut decoder, &[0x1E, 0x2A, 0x1E, 0x4F]);
+        assert_eq!(events[0].2, Some('A'));
+        assert_eq!(events[2].2, Some('a'));
+        assert_eq!(events[3].2, Some('1'));
+
+        decode_all(&mut decoder, &[0x3A, 0xBA]);
+        assert_eq!(decoder.leds(), LED_NUM_LOCK);
+
+        // Typematic repeats of a held lock key.
+        decode_all(&mut decoder, &[0x3A, 0x3A, 0x3A, 0xBA]);
+        assert_eq!(decoder.leds(), LED_CAPS_LOCK | LED_NUM_LOCK);
+    }
+
+    #[test]
+    fn leds_are_sent_on_acknowledgement() {
+        let mut leds = Leds::new();
+
+        assert_eq!(leds.update(LED_CAPS_LOCK), Some(SET_LEDS));
+        assert_eq!(leds.respond(ps2::RESEND), Some(SET_LEDS));
+        assert_eq!(leds.update(LED_NUM_LOCK), None);
+        assert_eq!(leds.respond(ps2::ACK), Some(LED_CAPS_LOCK));
+        assert_eq!(leds.respond(0x1E), None);
+
+        // The queued change starts the next command.
+        assert_eq!(leds.respond(ps2::ACK), Some(SET_LEDS));
+        assert_eq!(leds.respond(ps2::ACK), Some(LED_NUM_LOCK));
+        assert_eq!(leds.respond(ps2::ACK), None);
+        assert_eq!(leds.state, LedState::Idle);
+        assert_eq!(leds.respond(ps2::ACK), None);
+    }
+
+    #[test]
+    fn control_characters() {
+        let mut decoder = Decoder::new(ScancodeSet::Set1);
+        let events = decode_all(&mut decoder, &[0x1D, 0x2E]);
+
+        assert_eq!(events[1].2, Some('\x03'));
+    }
+
+    #[test]
+    fn device_responses_are_ignored() {
+        let mut decoder = Decoder::new(ScancodeSet::Set2);
+
+        assert!(decode_all(&mut decoder, &[ps2::ACK, ps2::RESEND, 0xAA]).is_empty());
+    }
+}
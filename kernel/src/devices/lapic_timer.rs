@@ -0,0 +1,206 @@
+//! Local APIC timer, the per-CPU clock event device.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use raw_cpuid::CpuId;
+use x86_64::registers::model_specific::Msr;
+
+use crate::devices::{cpu, local_apic::LOCAL_APIC};
+use crate::interrupts::vector;
+use crate::prelude::*;
+use crate::time::{self, NSEC_PER_SEC, USEC_PER_SEC};
+
+const LVT_TIMER: u32 = 0x320;
+const INITIAL_COUNT: u32 = 0x380;
+const CURRENT_COUNT: u32 = 0x390;
+const DIVIDE_CONFIG: u32 = 0x3E0;
+
+const IA32_TSC_DEADLINE: u32 = 0x6E0;
+
+const LVT_MASKED: u32 = 1 << 16;
+
+/// Divide the bus clock by 16.
+const DIVIDE_BY_16: u32 = 0b0011;
+
+/// Duration of the calibration against the reference clock.
+const CALIBRATION_US: u64 = 10_000;
+/// Timer frequency used when there is no reference clock.
+const DEFAULT_FREQUENCY: u64 = 62_500_000;
+
+/// Timer frequency in Hz after the divider, the same on all CPUs.
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// Periodic interrupts received by CPUs.
+static TICKS: [AtomicU64; KERNEL_MAX_CPUS] = [const { AtomicU64::new(0) }; KERNEL_MAX_CPUS];
+
+#[thread_local]
+static mut MODE: Mode = Mode::Stopped;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Mode {
+    Stopped,
+    Periodic,
+    OneShot,
+    TscDeadline,
+}
+
+impl Mode {
+    /// Timer mode bits of the LVT timer entry.
+    fn lvt_bits(self) -> u32 {
+        match self {
+            Mode::Stopped | Mode::OneShot => 0b00 << 17,
+            Mode::Periodic => 0b01 << 17,
+            Mode::TscDeadline => 0b10 << 17,
+        }
+    }
+}
+
+/// Measures the timer frequency against the reference clock, must be called once on the BSP with
+/// interrupts disabled.
+pub(super) fn calibrate() {
+    let frequency = unsafe {
+        LOCAL_APIC.write_register(DIVIDE_CONFIG, DIVIDE_BY_16);
+        LOCAL_APIC.write_register(LVT_TIMER, LVT_MASKED | u32::from(vector::LAPIC_TIMER));
+        LOCAL_APIC.write_register(INITIAL_COUNT, u32::MAX);
+
+        let reference = time::calibration_wait(CALIBRATION_US);
+        let elapsed = u32::MAX - LOCAL_APIC.read_register(CURRENT_COUNT);
+
+        LOCAL_APIC.write_register(INITIAL_COUNT, 0);
+
+        if let Some(reference) = reference {
+            log::debug!("LAPIC timer calibrated against {reference}");
+
+            u64::from(elapsed) * USEC_PER_SEC / CALIBRATION_US
+        } else {
+            // Core crystal clock drives the timer on CPUs without legacy PIT.
+            let crystal = CpuId::new()
+                .get_tsc_info()
+                .map_or(0, |info| u64::from(info.nominal_frequency()));
+
+            log::warn!("No reference clock, LAPIC timer frequency is not calibrated");
+
+            match crystal / 16 {
+                0 => DEFAULT_FREQUENCY,
+                frequency => frequency,
+            }
+        }
+    };
+
+    FREQUENCY.store(frequency, Ordering::SeqCst);
+
+    log::debug!(
+        "LAPIC timer frequency: {frequency} Hz, TSC deadline: {}",
+        has_tsc_deadline()
+    );
+}
+
+/// Starts periodic ticks on the current CPU.
+pub(super) fn init() {
+    unsafe { LOCAL_APIC.write_register(DIVIDE_CONFIG, DIVIDE_BY_16) };
+
+    set_periodic(KERNEL_TIMER_HZ);
+}
+
+/// Returns the timer frequency in Hz.
+pub fn frequency() -> u64 {
+    FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// Returns the number of periodic ticks of the CPU.
+pub fn ticks(cpu_id: u64) -> u64 {
+    TICKS
+        .get(cpu_id as usize)
+        .map_or(0, |ticks| ticks.load(Ordering::Relaxed))
+}
+
+/// Returns the current mode of the timer on the current CPU.
+#[allow(dead_code, reason = "only periodic ticks are used for now")]
+pub fn mode() -> Mode {
+    unsafe { MODE }
+}
+
+/// Returns `true` if the timer supports one-shot mode with TSC deadline.
+pub fn has_tsc_deadline() -> bool {
+    CpuId::new()
+        .get_feature_info()
+        .is_some_and(|finfo| finfo.has_tsc_deadline())
+}
+
+/// Raises the timer interrupt `hz` times per second on the current CPU.
+pub fn set_periodic(hz: u64) {
+    let count = (frequency() / hz.max(1)).clamp(1, u64::from(u32::MAX));
+
+    #[allow(clippy::cast_possible_truncation)]
+    unsafe {
+        program(Mode::Periodic, count as u32);
+    }
+}
+
+/// Raises the timer interrupt once after `ns` nanoseconds on the current CPU.
+#[allow(dead_code, reason = "for tickless idle, not implemented yet")]
+pub fn set_oneshot(ns: u64) {
+    let count = u128::from(frequency()) * u128::from(ns) / u128::from(NSEC_PER_SEC);
+
+    #[allow(clippy::cast_possible_truncation)]
+    unsafe {
+        program(Mode::OneShot, count.clamp(1, u128::from(u32::MAX)) as u32);
+    }
+}
+
+/// Raises the timer interrupt once the TSC reaches the deadline on the current CPU.
+///
+/// Returns `false` if TSC deadline mode is not supported.
+#[allow(dead_code, reason = "for tickless idle, not implemented yet")]
+pub fn set_tsc_deadline(deadline: u64) -> bool {
+    if !has_tsc_deadline() {
+        return false;
+    }
+
+    unsafe {
+        program(Mode::TscDeadline, 0);
+
+        // The LVT write must be visible before the deadline is armed in xAPIC mode.
+        core::arch::x86_64::_mm_mfence();
+        Msr::new(IA32_TSC_DEADLINE).write(deadline);
+    }
+
+    true
+}
+
+/// Stops the timer on the current CPU.
+#[allow(dead_code, reason = "for tickless idle, not implemented yet")]
+pub fn stop() {
+    unsafe {
+        if MODE == Mode::TscDeadline {
+            Msr::new(IA32_TSC_DEADLINE).write(0);
+        }
+
+        LOCAL_APIC.write_register(LVT_TIMER, LVT_MASKED | u32::from(vector::LAPIC_TIMER));
+        LOCAL_APIC.write_register(INITIAL_COUNT, 0);
+
+        MODE = Mode::Stopped;
+    }
+}
+
+/// Counts the tick, must be called from the timer interrupt.
+pub fn handle_interrupt() {
+    unsafe {
+        match MODE {
+            Mode::Periodic => {
+                if let Some(ticks) = TICKS.get(cpu::current_id() as usize) {
+                    ticks.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Mode::OneShot | Mode::TscDeadline => MODE = Mode::Stopped,
+            Mode::Stopped => (),
+        }
+    }
+}
+
+unsafe fn program(mode: Mode, count: u32) {
+    MODE = mode;
+
+    LOCAL_APIC.write_register(LVT_TIMER, mode.lvt_bits() | u32::from(vector::LAPIC_TIMER));
+    LOCAL_APIC.write_register(INITIAL_COUNT, count);
+}
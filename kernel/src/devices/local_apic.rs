@@ -1,21 +1,35 @@
 use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU64, Ordering};
 
 use x2apic::lapic;
+use x86_64::registers::model_specific::Msr;
 use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame};
 use x86_64::{PhysAddr, VirtAddr};
 
+use crate::interrupts::vector;
 use crate::memory::KERNEL_PAGE_MAPPER;
 
 pub static LOCAL_APIC: LocalApic = LocalApic::empty();
 
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+const ISR: u32 = 0x100;
+const ESR: u32 = 0x280;
+const LVT_PERF_COUNTER: u32 = 0x340;
+
+const LVT_DELIVERY_NMI: u32 = 0b100 << 8;
+
 pub struct LocalApic {
     inner: UnsafeCell<Option<lapic::LocalApic>>,
+    // Virtual address of xAPIC registers, zero in x2APIC mode.
+    xapic_base: AtomicU64,
 }
 
 impl LocalApic {
     const fn empty() -> Self {
         Self {
             inner: UnsafeCell::new(None),
+            xapic_base: AtomicU64::new(0),
         }
     }
 
@@ -25,12 +39,15 @@ impl LocalApic {
 
         if !super::cpu::has_x2apic() {
             unsafe { map_memory(apic_phys_addr, apic_virt_addr) };
+
+            self.xapic_base
+                .store(apic_virt_addr.as_u64(), Ordering::SeqCst);
         }
 
         let lapic = lapic::LocalApicBuilder::new()
-            .timer_vector(48)
-            .error_vector(49)
-            .spurious_vector(50)
+            .timer_vector(vector::LAPIC_TIMER.into())
+            .error_vector(vector::LAPIC_ERROR.into())
+            .spurious_vector(vector::SPURIOUS.into())
             .set_xapic_base(apic_virt_addr.as_u64())
             .build()
             .unwrap_or_else(|err| panic!("build Local APIC: {}", err));
@@ -57,6 +74,50 @@ impl LocalApic {
         }
     }
 
+    /// Returns `true` if the vector is in service and waits for end-of-interrupt.
+    pub fn is_in_service(&self, vector: u8) -> bool {
+        let offset = ISR + u32::from(vector / 32) * 0x10;
+
+        unsafe { self.read_register(offset) & (1 << (vector % 32)) != 0 }
+    }
+
+    /// Reads and clears errors from the error status register.
+    pub fn read_errors(&self) -> lapic::ErrorFlags {
+        unsafe {
+            // ESR must be written before reading to latch the errors collected so far.
+            self.write_register(ESR, 0);
+
+            #[allow(clippy::cast_possible_truncation)]
+            lapic::ErrorFlags::from_bits_truncate(self.read_register(ESR) as u8)
+        }
+    }
+
+    /// Delivers performance counter overflow interrupts as NMI, the entry is masked by the CPU on
+    /// every delivery, so it must be called again to receive the next one.
+    pub fn set_perf_counter_nmi(&self) {
+        unsafe { self.write_register(LVT_PERF_COUNTER, LVT_DELIVERY_NMI) };
+    }
+
+    /// Sends an NMI to the processor with the local APIC id.
+    pub fn send_nmi(&self, apic_id: u32) {
+        let x2apic = self.xapic_base.load(Ordering::Relaxed) == 0;
+
+        unsafe {
+            if let Some(Some(inner)) = self.inner.get().as_mut() {
+                inner.send_nmi(destination(apic_id, x2apic));
+            }
+        }
+    }
+
+    /// Sends an NMI to all processors except the current one.
+    pub fn send_nmi_others(&self) {
+        unsafe {
+            if let Some(Some(inner)) = self.inner.get().as_mut() {
+                inner.send_nmi_all(lapic::IpiAllShorthand::AllExcludingSelf);
+            }
+        }
+    }
+
     /// Sends an INIT IPI to the processors in dest
     pub unsafe fn send_init_ipi(&self, dest: u32) {
         if let Some(Some(inner)) = self.inner.get().as_mut() {
@@ -70,6 +131,21 @@ impl LocalApic {
             inner.send_sipi(vector, dest);
         }
     }
+
+    pub(super) unsafe fn read_register(&self, offset: u32) -> u32 {
+        match self.xapic_base.load(Ordering::Relaxed) {
+            #[allow(clippy::cast_possible_truncation)]
+            0 => Msr::new(X2APIC_MSR_BASE + (offset >> 4)).read() as u32,
+            base => ((base + u64::from(offset)) as *const u32).read_volatile(),
+        }
+    }
+
+    pub(super) unsafe fn write_register(&self, offset: u32, value: u32) {
+        match self.xapic_base.load(Ordering::Relaxed) {
+            0 => Msr::new(X2APIC_MSR_BASE + (offset >> 4)).write(u64::from(value)),
+            base => ((base + u64::from(offset)) as *mut u32).write_volatile(value),
+        }
+    }
 }
 
 unsafe impl Sync for LocalApic {}
@@ -85,3 +161,25 @@ unsafe fn map_memory(phys_addr: PhysAddr, virt_addr: VirtAddr) {
         mapper.map_phys(page, frame, flags).unwrap().flush();
     }
 }
+
+/// Encodes the destination of an IPI, xAPIC takes the APIC id in the top byte of the high ICR
+/// half, x2APIC takes the whole id.
+const fn destination(apic_id: u32, x2apic: bool) -> u32 {
+    if x2apic {
+        apic_id
+    } else {
+        apic_id << 24
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipi_destination() {
+        assert_eq!(destination(3, false), 0x0300_0000);
+        assert_eq!(destination(0xFF, false), 0xFF00_0000);
+        assert_eq!(destination(0x1234, true), 0x1234);
+    }
+}
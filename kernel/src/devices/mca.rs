@@ -0,0 +1,311 @@
+//! Machine check architecture: error reporting banks of the CPU.
+
+use core::fmt;
+
+use raw_cpuid::CpuId;
+use x86_64::registers::control::{Cr4, Cr4Flags};
+use x86_64::registers::model_specific::Msr;
+
+use crate::devices::cpu;
+use crate::prelude::*;
+
+const IA32_MCG_CAP: u32 = 0x179;
+const IA32_MCG_STATUS: u32 = 0x17A;
+const IA32_MCG_CTL: u32 = 0x17B;
+const IA32_MC0_CTL: u32 = 0x400;
+
+const MCG_CAP_COUNT: u64 = 0xFF;
+const MCG_CAP_CTL_P: u64 = 1 << 8;
+
+const MCG_STATUS_RIPV: u64 = 1 << 0;
+const MCG_STATUS_EIPV: u64 = 1 << 1;
+
+const STATUS_VAL: u64 = 1 << 63;
+const STATUS_OVER: u64 = 1 << 62;
+const STATUS_UC: u64 = 1 << 61;
+const STATUS_MISCV: u64 = 1 << 59;
+const STATUS_ADDRV: u64 = 1 << 58;
+const STATUS_PCC: u64 = 1 << 57;
+
+/// Bank errors kept by a machine check exception, more are counted only.
+const MAX_ERRORS: usize = 32;
+
+/// Timer ticks between polls of corrected errors.
+const POLL_INTERVAL_TICKS: u64 = 10 * KERNEL_TIMER_HZ;
+
+#[thread_local]
+static mut TICKS: u64 = 0;
+
+/// Enables all error reporting banks on the current CPU.
+pub(super) fn init() {
+    let supported = CpuId::new()
+        .get_feature_info()
+        .is_some_and(|finfo| finfo.has_mce() && finfo.has_mca());
+
+    if !supported {
+        log::debug!("CPU{} MCA not supported", cpu::current_id());
+        return;
+    }
+
+    let cap = unsafe { read_msr(IA32_MCG_CAP) };
+
+    unsafe {
+        if cap & MCG_CAP_CTL_P != 0 {
+            write_msr(IA32_MCG_CTL, u64::MAX);
+        }
+
+        for bank in banks(cap) {
+            // Status survives a warm reset, so it may contain the reason of the previous crash.
+            if let Some(err) = BankError::read(bank) {
+                log::warn!("CPU{} MCA error before boot: {err}", cpu::current_id());
+            }
+
+            write_msr(ctl_msr(bank), u64::MAX);
+            write_msr(ctl_msr(bank) + 1, 0);
+        }
+
+        Cr4::update(|flags| flags.insert(Cr4Flags::MACHINE_CHECK_EXCEPTION));
+    }
+
+    log::debug!(
+        "CPU{} MCA enabled, {} banks",
+        cpu::current_id(),
+        cap & MCG_CAP_COUNT
+    );
+}
+
+/// Reads errors of all banks on machine check exception.
+///
+/// Nothing is logged here, the exception may interrupt a CPU holding the console lock. Fatal
+/// checks are reported by the panic handler, corrected errors are left in banks for [`poll`].
+pub fn handle() -> MachineCheck {
+    let (cap, mcg_status) = unsafe { (read_msr(IA32_MCG_CAP), read_msr(IA32_MCG_STATUS)) };
+
+    let mut check = MachineCheck {
+        cpu_id: cpu::current_id(),
+        restart_ip_valid: mcg_status & MCG_STATUS_RIPV != 0,
+        error_ip_valid: mcg_status & MCG_STATUS_EIPV != 0,
+        errors: [None; MAX_ERRORS],
+        len: 0,
+        missed: 0,
+    };
+
+    for bank in banks(cap) {
+        // Uncorrected errors are kept in banks for the crash report after reboot.
+        if let Some(err) = unsafe { BankError::read(bank) } {
+            check.push(err);
+        }
+    }
+
+    if !check.is_fatal() {
+        unsafe { write_msr(IA32_MCG_STATUS, 0) };
+    }
+
+    check
+}
+
+/// Polls banks for corrected errors, including those found by the machine check exception, must
+/// be called from the timer interrupt.
+pub fn poll() {
+    unsafe {
+        TICKS += 1;
+
+        if !TICKS.is_multiple_of(POLL_INTERVAL_TICKS) {
+            return;
+        }
+    }
+
+    if !Cr4::read().contains(Cr4Flags::MACHINE_CHECK_EXCEPTION) {
+        return;
+    }
+
+    let cap = unsafe { read_msr(IA32_MCG_CAP) };
+
+    for bank in banks(cap) {
+        match unsafe { BankError::read(bank) } {
+            Some(err) if err.is_corrected() => {
+                log::warn!("CPU{} MCA {err}", cpu::current_id());
+                unsafe { err.clear() };
+            }
+            // Uncorrected errors are reported by the machine check exception.
+            _ => (),
+        }
+    }
+}
+
+/// Errors found by a machine check exception.
+#[derive(Debug, Copy, Clone)]
+pub struct MachineCheck {
+    pub cpu_id: u64,
+    pub restart_ip_valid: bool,
+    pub error_ip_valid: bool,
+    errors: [Option<BankError>; MAX_ERRORS],
+    len: usize,
+    // Errors which didn't fit.
+    missed: usize,
+}
+
+impl MachineCheck {
+    fn push(&mut self, err: BankError) {
+        match self.errors.get_mut(self.len) {
+            Some(slot) => {
+                *slot = Some(err);
+                self.len += 1;
+            }
+            None => self.missed += 1,
+        }
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &BankError> {
+        self.errors[..self.len].iter().flatten()
+    }
+
+    /// Returns `true` if the interrupted code can't continue or an error is uncorrected.
+    pub fn is_fatal(&self) -> bool {
+        !self.restart_ip_valid || self.missed != 0 || self.errors().any(|err| !err.is_corrected())
+    }
+}
+
+impl fmt::Display for MachineCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CPU{} machine check, restart IP valid: {}, error IP valid: {}",
+            self.cpu_id, self.restart_ip_valid, self.error_ip_valid
+        )?;
+
+        for err in self.errors() {
+            write!(f, "\nMCA {err}")?;
+        }
+
+        if self.missed != 0 {
+            write!(f, "\n{} more errors", self.missed)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Valid error logged by a bank.
+#[derive(Debug, Copy, Clone)]
+pub struct BankError {
+    pub bank: u8,
+    pub status: u64,
+    pub addr: Option<u64>,
+    pub misc: Option<u64>,
+}
+
+impl BankError {
+    unsafe fn read(bank: u8) -> Option<Self> {
+        let status = read_msr(ctl_msr(bank) + 1);
+
+        if status & STATUS_VAL == 0 {
+            return None;
+        }
+
+        Some(Self {
+            bank,
+            status,
+            addr: (status & STATUS_ADDRV != 0).then(|| read_msr(ctl_msr(bank) + 2)),
+            misc: (status & STATUS_MISCV != 0).then(|| read_msr(ctl_msr(bank) + 3)),
+        })
+    }
+
+    unsafe fn clear(&self) {
+        write_msr(ctl_msr(self.bank) + 1, 0);
+    }
+
+    pub fn is_corrected(&self) -> bool {
+        self.status & STATUS_UC == 0
+    }
+
+    /// Returns `true` if the processor context may be corrupted.
+    pub fn is_context_corrupt(&self) -> bool {
+        self.status & STATUS_PCC != 0
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn error_code(&self) -> u16 {
+        self.status as u16
+    }
+
+    /// Number of corrected errors, valid for corrected errors only.
+    pub fn corrected_count(&self) -> u64 {
+        (self.status >> 38) & 0x7FFF
+    }
+}
+
+impl fmt::Display for BankError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bank {}: ", self.bank)?;
+
+        if self.is_corrected() {
+            write!(f, "corrected ({} times)", self.corrected_count())?;
+        } else if self.is_context_corrupt() {
+            write!(f, "uncorrected, context corrupt")?;
+        } else {
+            write!(f, "uncorrected")?;
+        }
+
+        write!(
+            f,
+            " {} error {:#06x}, status {:#018x}",
+            error_kind(self.error_code()),
+            self.error_code(),
+            self.status
+        )?;
+
+        if let Some(addr) = self.addr {
+            write!(f, ", addr {addr:#x}")?;
+        }
+
+        if let Some(misc) = self.misc {
+            write!(f, ", misc {misc:#x}")?;
+        }
+
+        if self.status & STATUS_OVER != 0 {
+            write!(f, ", overflow")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Decodes the architectural MCA error code, the filtering bit 12 is ignored.
+fn error_kind(code: u16) -> &'static str {
+    match code & !(1 << 12) {
+        0x0000 => "no",
+        0x0001 => "unclassified",
+        0x0002 => "microcode ROM parity",
+        0x0003 => "external",
+        0x0004 => "FRC",
+        0x0005 => "internal parity",
+        0x0006 => "SMM handler code access",
+        0x0400 => "internal timer",
+        code if code & 0xFC00 == 0x0400 => "internal unclassified",
+        code if code & 0xEFFC == 0x000C => "generic cache hierarchy",
+        code if code & 0xEFF0 == 0x0010 => "TLB",
+        code if code & 0xEF80 == 0x0080 => "memory controller",
+        code if code & 0xEF00 == 0x0100 => "cache hierarchy",
+        code if code & 0xE800 == 0x0800 => "bus and interconnect",
+        _ => "unknown",
+    }
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn banks(cap: u64) -> core::ops::Range<u8> {
+    0..(cap & MCG_CAP_COUNT) as u8
+}
+
+/// `IA32_MCi_CTL`, followed by `STATUS`, `ADDR` and `MISC` registers of the bank.
+fn ctl_msr(bank: u8) -> u32 {
+    IA32_MC0_CTL + u32::from(bank) * 4
+}
+
+unsafe fn read_msr(msr: u32) -> u64 {
+    Msr::new(msr).read()
+}
+
+unsafe fn write_msr(msr: u32, value: u64) {
+    Msr::new(msr).write(value);
+}
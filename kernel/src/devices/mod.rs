@@ -4,14 +4,24 @@ use x86_64::VirtAddr;
 pub mod acpi;
 pub mod cpu;
 pub mod display;
+pub mod hpet;
 pub mod io_apic;
+pub mod keyboard;
+pub mod lapic_timer;
 pub mod local_apic;
+pub mod mca;
+pub mod mouse;
+pub mod msi;
+pub mod pci;
+pub mod pit;
+pub mod pm_timer;
+pub mod ps2;
 pub mod rtc;
 pub mod serial;
+pub mod tsc;
 
 pub fn init_early(info: Option<&'static mut FrameBuffer>) {
-    serial::COM1.lock().init();
-    serial::COM2.lock().init();
+    serial::init();
 
     if let Some(fb) = info {
         display::DISPLAY.lock().init(fb.info(), fb.buffer_mut());
@@ -24,12 +34,24 @@ pub fn init_early(info: Option<&'static mut FrameBuffer>) {
 pub fn init(phys_mem_offset: u64, rsdp_addr: Option<u64>) {
     let phys_mem_offset = VirtAddr::new(phys_mem_offset);
 
+    for (index, port) in serial::PORTS.iter().enumerate() {
+        // The logger writes to COM1, so the port must be unlocked.
+        let variant = port.lock().variant();
+
+        if let Some(variant) = variant {
+            log::debug!("COM{}: {variant:?}", index + 1);
+        }
+    }
+
     log::trace!("Disable pic");
     disable_pic();
 
     log::trace!("Init Local APIC");
     local_apic::LOCAL_APIC.init(phys_mem_offset);
 
+    log::trace!("Init MCA");
+    mca::init();
+
     if let Some(rsdp_addr) = rsdp_addr {
         log::trace!("Parse ACPI");
         acpi::ACPI.write().init(phys_mem_offset, rsdp_addr);
@@ -47,18 +69,63 @@ pub fn init(phys_mem_offset: u64, rsdp_addr: Option<u64>) {
             io_apic::IO_APICS.init(phys_mem_offset, bsp_apic_id, apic);
         }
 
+        log::trace!("Enumerate PCI devices");
+        pci::init();
+
         if let Some(century) = acpi_info.century_reg {
             log::trace!("Init RTC");
             rtc::RTC.lock().init(century);
         }
 
+        if let Some(info) = acpi_info.hpet.as_ref() {
+            log::trace!("Init HPET");
+            hpet::HPET.init(phys_mem_offset, info);
+        }
+
+        if let Some(info) = acpi_info.pm_timer.as_ref() {
+            log::trace!("Init PM timer");
+            pm_timer::PM_TIMER.init(info);
+        }
+    }
+
+    log::trace!("Init PS/2 controller");
+    let ports = ps2::CONTROLLER.lock().init();
+
+    if ports.keyboard {
+        let set = if ports.translation {
+            keyboard::ScancodeSet::Set1
+        } else {
+            keyboard::ScancodeSet::Set2
+        };
+
+        keyboard::init(set);
+    }
+
+    if ports.aux {
+        mouse::init();
+    }
+
+    // Calibrated against HPET or PM timer if present, so after ACPI.
+    log::trace!("Init TSC");
+    tsc::TSC.init();
+
+    log::trace!("Init LAPIC timer");
+    lapic_timer::calibrate();
+    lapic_timer::init();
+
+    crate::time::init();
+
+    if rsdp_addr.is_some() {
         log::trace!("Init AP cores");
-        cpu::init_ap_cores(phys_mem_offset, &acpi_info.ap_processors);
+        cpu::init_ap_cores(phys_mem_offset, &acpi::ACPI.read().ap_processors);
     }
 }
 
 pub fn init_ap() {
     local_apic::LOCAL_APIC.init_ap();
+    tsc::TSC.sync_ap();
+    lapic_timer::init();
+    mca::init();
 
     cpu::set_ap_is_ready();
 }
@@ -0,0 +1,278 @@
+//! PS/2 mouse on the aux port, with the IntelliMouse scroll wheel extension.
+
+use spin::{Mutex, Once};
+
+use crate::devices::ps2::{self, Device};
+use crate::input::{self, Button, DeviceId, DeviceKind, Event};
+
+pub static MOUSE: Mutex<Mouse> = Mutex::new(Mouse::new(false));
+static DEVICE: Once<DeviceId> = Once::new();
+
+const GET_ID: u8 = 0xF2;
+const SET_SAMPLE_RATE: u8 = 0xF3;
+const ENABLE_REPORTING: u8 = 0xF4;
+const SET_DEFAULTS: u8 = 0xF6;
+
+const ID_STANDARD: u8 = 0x00;
+const ID_INTELLIMOUSE: u8 = 0x03;
+
+/// Sample rates unlocking the wheel of an IntelliMouse.
+const INTELLIMOUSE_KNOCK: [u8; 3] = [200, 100, 80];
+const SAMPLE_RATE: u8 = 100;
+
+const PACKET_LEFT: u8 = 1 << 0;
+const PACKET_RIGHT: u8 = 1 << 1;
+const PACKET_MIDDLE: u8 = 1 << 2;
+const PACKET_ALWAYS_ONE: u8 = 1 << 3;
+const PACKET_X_SIGN: u8 = 1 << 4;
+const PACKET_Y_SIGN: u8 = 1 << 5;
+const PACKET_X_OVERFLOW: u8 = 1 << 6;
+const PACKET_Y_OVERFLOW: u8 = 1 << 7;
+
+const BUTTONS: [(u8, Button); 3] = [
+    (PACKET_LEFT, Button::Left),
+    (PACKET_RIGHT, Button::Right),
+    (PACKET_MIDDLE, Button::Middle),
+];
+
+/// Resets the mouse, detects the scroll wheel and enables reporting, must be called with
+/// interrupts disabled.
+pub(super) fn init() {
+    let mut controller = ps2::CONTROLLER.lock();
+
+    // The self-test result is followed by the ID.
+    if !controller.reset(Device::Aux) || controller.receive() != Some(ID_STANDARD) {
+        log::warn!("PS/2 mouse: reset failed");
+        return;
+    }
+
+    let knocked = INTELLIMOUSE_KNOCK
+        .iter()
+        .all(|rate| set_sample_rate(&mut controller, *rate));
+
+    let wheel = knocked && read_id(&mut controller) == Some(ID_INTELLIMOUSE);
+
+    if !(set_sample_rate(&mut controller, SAMPLE_RATE)
+        && controller.command(Device::Aux, ENABLE_REPORTING))
+    {
+        log::warn!("PS/2 mouse: failed to enable reporting");
+        controller.command(Device::Aux, SET_DEFAULTS);
+        return;
+    }
+
+    *MOUSE.lock() = Mouse::new(wheel);
+    DEVICE.call_once(|| input::register("PS/2 mouse", DeviceKind::Mouse));
+
+    log::debug!("PS/2 mouse: wheel {wheel}");
+}
+
+/// Assembles packets from the byte received from the mouse and queues their events, must be
+/// called from the interrupt handler.
+pub fn handle_byte(byte: u8) {
+    let Some(device) = DEVICE.get() else {
+        return;
+    };
+
+    let mut mouse = MOUSE.lock();
+
+    if let Some(packet) = mouse.decode(byte) {
+        mouse.events(packet, |event| input::push(*device, event));
+    }
+}
+
+fn set_sample_rate(controller: &mut ps2::Controller, rate: u8) -> bool {
+    controller.command(Device::Aux, SET_SAMPLE_RATE) && controller.command(Device::Aux, rate)
+}
+
+fn read_id(controller: &mut ps2::Controller) -> Option<u8> {
+    if !controller.command(Device::Aux, GET_ID) {
+        return None;
+    }
+
+    controller.receive()
+}
+
+/// Decoded movement packet.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Packet {
+    /// Pressed buttons as `PACKET_*` bits.
+    pub buttons: u8,
+    pub dx: i16,
+    /// Grows upwards as reported by the mouse.
+    pub dy: i16,
+    /// Positive when scrolled towards the user.
+    pub dz: i8,
+}
+
+pub struct Mouse {
+    wheel: bool,
+    bytes: [u8; 4],
+    len: usize,
+    // Buttons of the previous packet.
+    buttons: u8,
+}
+
+impl Mouse {
+    pub const fn new(wheel: bool) -> Self {
+        Self {
+            wheel,
+            bytes: [0; 4],
+            len: 0,
+            buttons: 0,
+        }
+    }
+
+    #[allow(dead_code, reason = "the wheel is only logged at init for now")]
+    pub fn has_wheel(&self) -> bool {
+        self.wheel
+    }
+
+    fn packet_len(&self) -> usize {
+        if self.wheel {
+            4
+        } else {
+            3
+        }
+    }
+
+    /// Feeds the byte, returns the packet once it's complete.
+    ///
+    /// Bytes are dropped until one looks like the first of a packet, so the decoder resyncs after
+    /// a lost byte.
+    pub fn decode(&mut self, byte: u8) -> Option<Packet> {
+        if self.len == 0 && byte & PACKET_ALWAYS_ONE == 0 {
+            return None;
+        }
+
+        self.bytes[self.len] = byte;
+        self.len += 1;
+
+        if self.len < self.packet_len() {
+            return None;
+        }
+
+        self.len = 0;
+
+        let [flags, x, y, z] = self.bytes;
+
+        // Overflowed movement is meaningless.
+        let dx = if flags & PACKET_X_OVERFLOW == 0 {
+            movement(x, flags & PACKET_X_SIGN != 0)
+        } else {
+            0
+        };
+
+        let dy = if flags & PACKET_Y_OVERFLOW == 0 {
+            movement(y, flags & PACKET_Y_SIGN != 0)
+        } else {
+            0
+        };
+
+        Some(Packet {
+            buttons: flags & (PACKET_LEFT | PACKET_RIGHT | PACKET_MIDDLE),
+            dx,
+            dy,
+            dz: if self.wheel { z as i8 } else { 0 },
+        })
+    }
+
+    /// Passes the events of the packet to `emit`: motion, changed buttons then the wheel.
+    pub fn events(&mut self, packet: Packet, mut emit: impl FnMut(Event)) {
+        if packet.dx != 0 || packet.dy != 0 {
+            emit(Event::Motion {
+                dx: packet.dx,
+                dy: -packet.dy,
+            });
+        }
+
+        let changed = self.buttons ^ packet.buttons;
+        self.buttons = packet.buttons;
+
+        for (bit, button) in BUTTONS {
+            if changed & bit != 0 {
+                emit(Event::Button {
+                    button,
+                    pressed: packet.buttons & bit != 0,
+                });
+            }
+        }
+
+        if packet.dz != 0 {
+            emit(Event::Wheel(packet.dz.saturating_neg()));
+        }
+    }
+}
+
+/// Sign extends 9-bit movement.
+fn movement(value: u8, negative: bool) -> i16 {
+    if negative {
+        i16::from(value) - 0x100
+    } else {
+        i16::from(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(mouse: &mut Mouse, bytes: &[u8]) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        for byte in bytes {
+            if let Some(packet) = mouse.decode(*byte) {
+                mouse.events(packet, |event| events.push(event));
+            }
+        }
+
+        events
+    }
+
+    #[test]
+    fn standard_packets() {
+        let mut mouse = Mouse::new(false);
+
+        // Right and up, then left button pressed while moving left and down.
+        let events = decode_all(&mut mouse, &[0x08, 5, 3, 0x39, 0xFE, 0xFC]);
+
+        assert_eq!(
+            events,
+            [
+                Event::Motion { dx: 5, dy: -3 },
+                Event::Motion { dx: -2, dy: 4 },
+                Event::Button {
+                    button: Button::Left,
+                    pressed: true
+                },
+            ]
+        );
+
+        let events = decode_all(&mut mouse, &[0x08, 0, 0]);
+        assert_eq!(
+            events,
+            [Event::Button {
+                button: Button::Left,
+                pressed: false
+            }]
+        );
+    }
+
+    #[test]
+    fn wheel_packets() {
+        let mut mouse = Mouse::new(true);
+
+        let events = decode_all(&mut mouse, &[0x08, 0, 0, 0xFF, 0x08, 0, 0, 0x01]);
+
+        assert_eq!(events, [Event::Wheel(1), Event::Wheel(-1)]);
+    }
+
+    #[test]
+    fn overflow_and_resync() {
+        let mut mouse = Mouse::new(false);
+
+        // A stray byte without the always one bit is dropped.
+        let events = decode_all(&mut mouse, &[0x00, 0x48, 0xFF, 7]);
+
+        assert_eq!(events, [Event::Motion { dx: 0, dy: -7 }]);
+    }
+}
@@ -0,0 +1,221 @@
+use alloc::vec::Vec;
+
+use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::devices::pci::{PciDevice, CAP_MSI, CAP_MSIX};
+use crate::interrupts::{irq, vector};
+use crate::memory::KERNEL_PAGE_MAPPER;
+use crate::prelude::*;
+
+/// Base of the message address, the destination APIC id is placed in bits 12-19.
+const MSG_ADDR_BASE: u32 = 0xFEE0_0000;
+
+const MSI_CTRL_ENABLE: u16 = 1 << 0;
+const MSI_CTRL_64BIT: u16 = 1 << 7;
+/// Most messages a function can request with MSI.
+const MSI_MAX_MESSAGES: u8 = 32;
+
+const MSIX_CTRL_FUNCTION_MASK: u16 = 1 << 14;
+const MSIX_CTRL_ENABLE: u16 = 1 << 15;
+const MSIX_ENTRY_SIZE: u64 = 16;
+const MSIX_ENTRY_MASKED: u32 = 1 << 0;
+
+/// Enables MSI on the device and routes up to `count` messages to the CPU.
+///
+/// The count is rounded up to a power of two and limited by the device capability. Message `n`
+/// raises vector `base + n`, where `base` is returned. The handler is called for every message.
+#[allow(dead_code, reason = "no PCI driver uses MSI yet")]
+pub fn enable_msi(
+    device: &PciDevice,
+    cpu_id: u64,
+    count: u8,
+    name: &'static str,
+    handler: irq::Handler,
+) -> Option<u8> {
+    let cap = device.find_capability(CAP_MSI)?;
+    let pci = device.address;
+
+    let ctrl = pci.read_u16(cap + 2);
+    // Encodings above 32 messages are reserved.
+    let capable = (1 << ((ctrl >> 1) & 0x7)).min(MSI_MAX_MESSAGES);
+    let count = count.min(MSI_MAX_MESSAGES).next_power_of_two().min(capable);
+
+    let base = vector::allocate(count, name)?;
+
+    for vector in base..base + count {
+        irq::register(vector, handler);
+    }
+
+    pci.write(cap + 4, message_address(cpu_id));
+
+    if ctrl & MSI_CTRL_64BIT == 0 {
+        pci.write_u16(cap + 8, message_data(base));
+    } else {
+        pci.write(cap + 8, 0);
+        pci.write_u16(cap + 12, message_data(base));
+    }
+
+    // Multiple message enable is log2 of messages count.
+    let enabled = u16::try_from(count.trailing_zeros()).unwrap();
+    let ctrl = (ctrl & !(0x7 << 4)) | enabled << 4 | MSI_CTRL_ENABLE;
+
+    pci.write_u16(cap + 2, ctrl);
+    device.disable_intx();
+
+    log::debug!(
+        "PCI {pci}: MSI enabled, vectors {base}..{}, CPU{cpu_id}",
+        base + count
+    );
+
+    Some(base)
+}
+
+/// Disables MSI on the device and releases vectors allocated by [`enable_msi`].
+#[allow(dead_code, reason = "no PCI driver uses MSI yet")]
+pub fn disable_msi(device: &PciDevice) {
+    let Some(cap) = device.find_capability(CAP_MSI) else {
+        return;
+    };
+    let pci = device.address;
+
+    let ctrl = pci.read_u16(cap + 2);
+
+    if ctrl & MSI_CTRL_ENABLE == 0 {
+        return;
+    }
+
+    pci.write_u16(cap + 2, ctrl & !MSI_CTRL_ENABLE);
+
+    let count = 1 << ((ctrl >> 4) & 0x7);
+    let data = if ctrl & MSI_CTRL_64BIT == 0 {
+        pci.read_u16(cap + 8)
+    } else {
+        pci.read_u16(cap + 12)
+    };
+    let base = u8::try_from(data & 0xFF).unwrap();
+
+    for vector in base..base + count {
+        irq::unregister(vector);
+    }
+
+    vector::free(base, count);
+}
+
+/// Enables MSI-X on the device and routes up to `count` table entries to the CPU.
+///
+/// Entry `n` raises `vectors[n]` of the returned list. The handler is called for every entry.
+#[allow(dead_code, reason = "no PCI driver uses MSI yet")]
+pub fn enable_msix(
+    device: &PciDevice,
+    cpu_id: u64,
+    count: u16,
+    name: &'static str,
+    handler: irq::Handler,
+) -> Option<Vec<u8>> {
+    let cap = device.find_capability(CAP_MSIX)?;
+    let pci = device.address;
+
+    let ctrl = pci.read_u16(cap + 2);
+    let count = count.min((ctrl & 0x7FF) + 1);
+
+    let table = pci.read(cap + 4);
+    let bar = device.memory_bar(u8::try_from(table & 0x7).unwrap())?;
+    let table_phys = PhysAddr::new(bar + u64::from(table & !0x7));
+    let table_virt = unsafe { map_table(table_phys, u64::from(count) * MSIX_ENTRY_SIZE) };
+
+    // Mask all entries until the table is programmed.
+    pci.write_u16(cap + 2, ctrl | MSIX_CTRL_ENABLE | MSIX_CTRL_FUNCTION_MASK);
+
+    let mut vectors = Vec::with_capacity(usize::from(count));
+
+    for entry in 0..u64::from(count) {
+        let Some(vector) = vector::allocate(1, name) else {
+            break;
+        };
+
+        irq::register(vector, handler);
+
+        let entry = (table_virt + entry * MSIX_ENTRY_SIZE).as_mut_ptr::<u32>();
+
+        unsafe {
+            entry.write_volatile(message_address(cpu_id));
+            entry.add(1).write_volatile(0);
+            entry.add(2).write_volatile(u32::from(message_data(vector)));
+            entry
+                .add(3)
+                .write_volatile(entry.add(3).read_volatile() & !MSIX_ENTRY_MASKED);
+        }
+
+        vectors.push(vector);
+    }
+
+    pci.write_u16(
+        cap + 2,
+        (ctrl | MSIX_CTRL_ENABLE) & !MSIX_CTRL_FUNCTION_MASK,
+    );
+    device.disable_intx();
+
+    log::debug!("PCI {pci}: MSI-X enabled, vectors {vectors:?}, CPU{cpu_id}");
+
+    Some(vectors)
+}
+
+/// Disables MSI-X on the device and releases vectors allocated by [`enable_msix`].
+#[allow(dead_code, reason = "no PCI driver uses MSI yet")]
+pub fn disable_msix(device: &PciDevice, vectors: &[u8]) {
+    let Some(cap) = device.find_capability(CAP_MSIX) else {
+        return;
+    };
+    let pci = device.address;
+
+    let ctrl = pci.read_u16(cap + 2);
+    pci.write_u16(cap + 2, ctrl & !MSIX_CTRL_ENABLE);
+
+    for vector in vectors {
+        irq::unregister(*vector);
+        vector::free(*vector, 1);
+    }
+}
+
+/// Physical destination mode, fixed delivery to the local APIC with the id of the CPU.
+#[allow(clippy::cast_possible_truncation)]
+pub(super) fn message_address(cpu_id: u64) -> u32 {
+    MSG_ADDR_BASE | (cpu_id as u32 & 0xFF) << 12
+}
+
+/// Edge triggered, fixed delivery mode.
+pub(super) fn message_data(vector: u8) -> u16 {
+    u16::from(vector)
+}
+
+unsafe fn map_table(phys_addr: PhysAddr, size: u64) -> VirtAddr {
+    let phys_offset = crate::PHYS_OFFSET
+        .get()
+        .copied()
+        .expect("Physical memory offset should be initialized");
+
+    let virt_addr = VirtAddr::new(phys_offset) + phys_addr.as_u64();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+
+    let page_range = Page::range_inclusive(
+        Page::containing_address(virt_addr),
+        Page::containing_address(virt_addr + size - 1u64),
+    );
+
+    let mut mapper = KERNEL_PAGE_MAPPER.lock();
+
+    for page in page_range {
+        let offset = page.start_address() - virt_addr.align_down(PAGE_SIZE);
+        let frame = PhysFrame::containing_address(phys_addr.align_down(PAGE_SIZE) + offset);
+
+        if mapper.translate(page.start_address()).is_none() {
+            mapper
+                .map_phys(page, frame, flags)
+                .expect("failed to map MSI-X table")
+                .flush();
+        }
+    }
+
+    virt_addr
+}
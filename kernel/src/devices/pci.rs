@@ -0,0 +1,242 @@
+use alloc::vec::Vec;
+
+use spin::{Mutex, RwLock};
+use x86_64::instructions::port::Port;
+
+pub static PCI_DEVICES: RwLock<Vec<PciDevice>> = RwLock::new(Vec::new());
+
+static CONFIG_SPACE: Mutex<ConfigSpace> = Mutex::new(ConfigSpace::new(0xCF8, 0xCFC));
+
+const VENDOR_NONE: u16 = 0xFFFF;
+
+const REG_VENDOR_ID: u8 = 0x00;
+const REG_COMMAND: u8 = 0x04;
+const REG_STATUS: u8 = 0x06;
+const REG_CLASS: u8 = 0x08;
+const REG_HEADER_TYPE: u8 = 0x0E;
+const REG_BAR0: u8 = 0x10;
+const REG_CAPABILITIES: u8 = 0x34;
+
+const COMMAND_INTX_DISABLE: u16 = 1 << 10;
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+const HEADER_MULTI_FUNCTION: u8 = 0x80;
+
+pub const CAP_MSI: u8 = 0x05;
+pub const CAP_MSIX: u8 = 0x11;
+
+/// Enumerates all devices on the PCI buses.
+pub(super) fn init() {
+    let mut devices = PCI_DEVICES.write();
+
+    for bus in 0..=u8::MAX {
+        for device in 0..32 {
+            let address = PciAddress::new(bus, device, 0);
+
+            let Some(dev) = PciDevice::probe(address) else {
+                continue;
+            };
+
+            let functions = if dev.header_type & HEADER_MULTI_FUNCTION == 0 {
+                1
+            } else {
+                8
+            };
+
+            devices.push(dev);
+
+            for function in 1..functions {
+                if let Some(dev) = PciDevice::probe(PciAddress::new(bus, device, function)) {
+                    devices.push(dev);
+                }
+            }
+        }
+    }
+
+    for dev in devices.iter() {
+        log::debug!(
+            "PCI {}: {:04x}:{:04x} class {:02x}:{:02x}:{:02x}",
+            dev.address,
+            dev.vendor_id,
+            dev.device_id,
+            dev.class,
+            dev.subclass,
+            dev.prog_if,
+        );
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PciAddress {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    pub const fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self {
+            bus,
+            device,
+            function,
+        }
+    }
+
+    pub fn read(&self, offset: u8) -> u32 {
+        CONFIG_SPACE.lock().read(*self, offset)
+    }
+
+    pub fn write(&self, offset: u8, value: u32) {
+        CONFIG_SPACE.lock().write(*self, offset, value);
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn read_u16(&self, offset: u8) -> u16 {
+        (self.read(offset & !3) >> ((offset & 2) * 8)) as u16
+    }
+
+    pub fn write_u16(&self, offset: u8, value: u16) {
+        let shift = (offset & 2) * 8;
+
+        let mut dword = self.read(offset & !3);
+        dword &= !(0xFFFF << shift);
+        dword |= u32::from(value) << shift;
+
+        self.write(offset & !3, dword);
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn read_u8(&self, offset: u8) -> u8 {
+        (self.read(offset & !3) >> ((offset & 3) * 8)) as u8
+    }
+
+    fn config_address(self, offset: u8) -> u32 {
+        0x8000_0000
+            | u32::from(self.bus) << 16
+            | u32::from(self.device) << 11
+            | u32::from(self.function) << 8
+            | u32::from(offset & 0xFC)
+    }
+}
+
+impl core::fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct PciDevice {
+    pub address: PciAddress,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub header_type: u8,
+}
+
+impl PciDevice {
+    #[allow(clippy::cast_possible_truncation)]
+    fn probe(address: PciAddress) -> Option<Self> {
+        let id = address.read(REG_VENDOR_ID);
+
+        if id as u16 == VENDOR_NONE {
+            return None;
+        }
+
+        let class = address.read(REG_CLASS);
+
+        Some(Self {
+            address,
+            vendor_id: id as u16,
+            device_id: (id >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            header_type: address.read_u8(REG_HEADER_TYPE),
+        })
+    }
+
+    /// Returns the offset of the capability in the config space.
+    pub fn find_capability(&self, id: u8) -> Option<u8> {
+        if self.address.read_u16(REG_STATUS) & STATUS_CAPABILITIES == 0 {
+            return None;
+        }
+
+        let mut offset = self.address.read_u8(REG_CAPABILITIES) & !3;
+
+        // Capabilities list fits in 256 bytes of config space, the limit protects from loops.
+        for _ in 0..48 {
+            if offset == 0 {
+                return None;
+            }
+
+            if self.address.read_u8(offset) == id {
+                return Some(offset);
+            }
+
+            offset = self.address.read_u8(offset + 1) & !3;
+        }
+
+        None
+    }
+
+    /// Returns the physical address of the memory BAR.
+    pub fn memory_bar(&self, index: u8) -> Option<u64> {
+        if index > 5 {
+            return None;
+        }
+
+        let offset = REG_BAR0 + index * 4;
+        let low = self.address.read(offset);
+
+        // I/O space BAR.
+        if low & 1 != 0 {
+            return None;
+        }
+
+        let high = match (low >> 1) & 3 {
+            0 => 0,
+            2 if index < 5 => self.address.read(offset + 4),
+            _ => return None,
+        };
+
+        Some(u64::from(high) << 32 | u64::from(low & !0xF))
+    }
+
+    /// Disables legacy INTx interrupts, should be done once MSI or MSI-X is enabled.
+    pub fn disable_intx(&self) {
+        let command = self.address.read_u16(REG_COMMAND);
+
+        self.address
+            .write_u16(REG_COMMAND, command | COMMAND_INTX_DISABLE);
+    }
+}
+
+struct ConfigSpace {
+    addr: Port<u32>,
+    data: Port<u32>,
+}
+
+impl ConfigSpace {
+    const fn new(addr: u16, data: u16) -> Self {
+        Self {
+            addr: Port::new(addr),
+            data: Port::new(data),
+        }
+    }
+
+    fn read(&mut self, address: PciAddress, offset: u8) -> u32 {
+        unsafe {
+            self.addr.write(address.config_address(offset));
+            self.data.read()
+        }
+    }
+
+    fn write(&mut self, address: PciAddress, offset: u8, value: u32) {
+        unsafe {
+            self.addr.write(address.config_address(offset));
+            self.data.write(value);
+        }
+    }
+}
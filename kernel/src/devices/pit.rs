@@ -0,0 +1,66 @@
+//! Programmable interval timer, used as a reference clock for calibration.
+
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+pub static PIT: Mutex<Pit> = Mutex::new(Pit::new());
+
+/// Input clock frequency in Hz.
+pub const FREQUENCY: u64 = 1_193_182;
+
+/// Longest wait with a 16-bit counter, about 54 ms.
+pub const MAX_WAIT_US: u64 = 0xFFFF * 1_000_000 / FREQUENCY;
+
+/// Channel 2, lobyte/hibyte access, mode 0 (interrupt on terminal count), binary.
+const CHANNEL2_ONESHOT: u8 = 0b1011_0000;
+
+const GATE_ENABLE: u8 = 1 << 0;
+const SPEAKER_ENABLE: u8 = 1 << 1;
+const OUT2_STATUS: u8 = 1 << 5;
+
+/// Bounds polling of the output, the PIT may be missing on legacy free hardware.
+const MAX_POLLS: usize = 100_000_000;
+
+pub struct Pit {
+    channel2: Port<u8>,
+    command: Port<u8>,
+    gate: Port<u8>,
+}
+
+impl Pit {
+    const fn new() -> Self {
+        Self {
+            channel2: Port::new(0x42),
+            command: Port::new(0x43),
+            gate: Port::new(0x61),
+        }
+    }
+
+    /// Busy waits for the duration with channel 2, limited to [`MAX_WAIT_US`].
+    ///
+    /// Returns `false` if the counter didn't expire, so the PIT is not present.
+    pub fn wait_us(&mut self, us: u64) -> bool {
+        let count = (us.min(MAX_WAIT_US) * FREQUENCY / 1_000_000).max(1);
+        let [low, high] = u16::try_from(count).unwrap_or(u16::MAX).to_le_bytes();
+
+        unsafe {
+            // Gate off, so counting starts once the count is loaded and the gate is raised.
+            let gate = self.gate.read() & !(GATE_ENABLE | SPEAKER_ENABLE);
+            self.gate.write(gate);
+
+            self.command.write(CHANNEL2_ONESHOT);
+            self.channel2.write(low);
+            self.channel2.write(high);
+
+            self.gate.write(gate | GATE_ENABLE);
+
+            for _ in 0..MAX_POLLS {
+                if self.gate.read() & OUT2_STATUS != 0 {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+}
@@ -0,0 +1,85 @@
+//! ACPI power management timer, a fallback reference clock for calibration.
+
+use core::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+
+use acpi::platform;
+use acpi::platform::address::AddressSpace;
+use x86_64::instructions::port::PortReadOnly;
+
+use crate::time::ClockSource;
+
+pub static PM_TIMER: PmTimer = PmTimer::empty();
+
+/// Counter frequency in Hz, the same on all machines.
+pub const FREQUENCY: u64 = 3_579_545;
+
+pub struct PmTimer {
+    // I/O port of the counter, zero if the timer is not present.
+    port: AtomicU16,
+    counter_mask: AtomicU64,
+}
+
+impl PmTimer {
+    const fn empty() -> Self {
+        Self {
+            port: AtomicU16::new(0),
+            counter_mask: AtomicU64::new(0),
+        }
+    }
+
+    pub(super) fn init(&self, info: &platform::PmTimer) {
+        if !matches!(info.base.address_space, AddressSpace::SystemIo) {
+            log::warn!(
+                "PM timer: unsupported address space {:?}",
+                info.base.address_space
+            );
+            return;
+        }
+
+        let Ok(port) = u16::try_from(info.base.address) else {
+            log::warn!("PM timer: invalid port {:#x}", info.base.address);
+            return;
+        };
+
+        let counter_mask = if info.supports_32bit {
+            u64::from(u32::MAX)
+        } else {
+            0xFF_FFFF
+        };
+
+        self.counter_mask.store(counter_mask, Ordering::SeqCst);
+        self.port.store(port, Ordering::SeqCst);
+
+        log::debug!(
+            "PM timer: port {port:#x}, {}-bit counter",
+            counter_mask.count_ones()
+        );
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.port.load(Ordering::Relaxed) != 0
+    }
+}
+
+impl ClockSource for PmTimer {
+    fn name(&self) -> &'static str {
+        "PM timer"
+    }
+
+    fn frequency(&self) -> u64 {
+        FREQUENCY
+    }
+
+    fn mask(&self) -> u64 {
+        self.counter_mask.load(Ordering::Relaxed)
+    }
+
+    fn read(&self) -> u64 {
+        if !self.is_present() {
+            return 0;
+        }
+
+        let mut port = PortReadOnly::<u32>::new(self.port.load(Ordering::Relaxed));
+        u64::from(unsafe { port.read() }) & self.mask()
+    }
+}
@@ -0,0 +1,283 @@
+//! i8042 PS/2 controller, the first port is used by the keyboard, the second (aux) by the mouse.
+
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+pub static CONTROLLER: Mutex<Controller> = Mutex::new(Controller::new());
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+const STATUS_AUX_DATA: u8 = 1 << 5;
+
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_DISABLE_AUX: u8 = 0xA7;
+const CMD_ENABLE_AUX: u8 = 0xA8;
+const CMD_TEST_AUX: u8 = 0xA9;
+const CMD_SELF_TEST: u8 = 0xAA;
+const CMD_TEST_KEYBOARD: u8 = 0xAB;
+const CMD_DISABLE_KEYBOARD: u8 = 0xAD;
+const CMD_ENABLE_KEYBOARD: u8 = 0xAE;
+const CMD_WRITE_AUX: u8 = 0xD4;
+
+const CONFIG_KEYBOARD_INT: u8 = 1 << 0;
+const CONFIG_AUX_INT: u8 = 1 << 1;
+const CONFIG_KEYBOARD_CLOCK_OFF: u8 = 1 << 4;
+const CONFIG_AUX_CLOCK_OFF: u8 = 1 << 5;
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+/// Device responses.
+pub const ACK: u8 = 0xFA;
+pub const RESEND: u8 = 0xFE;
+const DEVICE_RESET: u8 = 0xFF;
+const DEVICE_TEST_PASSED: u8 = 0xAA;
+
+/// Bounds polling of the status, the controller may be missing on legacy free hardware.
+const MAX_POLLS: usize = 100_000;
+/// Attempts of a device command answered with resend.
+const MAX_RESENDS: usize = 3;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Device {
+    Keyboard,
+    Aux,
+}
+
+/// Ports found working by [`Controller::init`].
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Ports {
+    pub keyboard: bool,
+    pub aux: bool,
+    /// The controller translates keyboard scancodes to set 1.
+    pub translation: bool,
+}
+
+pub struct Controller {
+    data: Port<u8>,
+    // Status on read, command on write.
+    command: Port<u8>,
+    ports: Ports,
+}
+
+impl Controller {
+    const fn new() -> Self {
+        Self {
+            data: Port::new(0x60),
+            command: Port::new(0x64),
+            ports: Ports {
+                keyboard: false,
+                aux: false,
+                translation: false,
+            },
+        }
+    }
+
+    /// Tests the controller and its ports, resets the keyboard and enables interrupts of working
+    /// ports, must be called with interrupts disabled.
+    pub(super) fn init(&mut self) -> Ports {
+        self.ports = Ports::default();
+
+        unsafe {
+            self.send_command(CMD_DISABLE_KEYBOARD);
+            self.send_command(CMD_DISABLE_AUX);
+            self.flush();
+
+            let Some(config) = self.read_config() else {
+                log::warn!("PS/2 controller is not present");
+                return self.ports;
+            };
+
+            let config = config & !(CONFIG_KEYBOARD_INT | CONFIG_AUX_INT);
+            self.write_config(config);
+
+            self.send_command(CMD_SELF_TEST);
+
+            if self.read_data() != Some(SELF_TEST_PASSED) {
+                log::warn!("PS/2 controller self-test failed");
+                return self.ports;
+            }
+
+            // The self-test may reset the controller.
+            self.write_config(config);
+
+            // The aux clock is enabled by the command only on dual port controllers.
+            self.send_command(CMD_ENABLE_AUX);
+            let dual = self
+                .read_config()
+                .is_some_and(|config| config & CONFIG_AUX_CLOCK_OFF == 0);
+            self.send_command(CMD_DISABLE_AUX);
+
+            self.send_command(CMD_TEST_KEYBOARD);
+            let keyboard = self.read_data() == Some(PORT_TEST_PASSED);
+
+            let aux = dual && {
+                self.send_command(CMD_TEST_AUX);
+                self.read_data() == Some(PORT_TEST_PASSED)
+            };
+
+            let mut config = config;
+
+            if keyboard {
+                self.send_command(CMD_ENABLE_KEYBOARD);
+                config = (config & !CONFIG_KEYBOARD_CLOCK_OFF) | CONFIG_KEYBOARD_INT;
+            }
+
+            if aux {
+                self.send_command(CMD_ENABLE_AUX);
+                config = (config & !CONFIG_AUX_CLOCK_OFF) | CONFIG_AUX_INT;
+            }
+
+            // Reset the keyboard before interrupts are enabled, so its response is polled.
+            let keyboard = keyboard && self.reset(Device::Keyboard);
+
+            self.write_config(config);
+
+            self.ports = Ports {
+                keyboard,
+                aux,
+                translation: config & CONFIG_TRANSLATION != 0,
+            };
+        }
+
+        log::debug!("PS/2 ports: {:?}", self.ports);
+
+        self.ports
+    }
+
+    #[allow(dead_code, reason = "the ports are only logged for now")]
+    pub fn ports(&self) -> Ports {
+        self.ports
+    }
+
+    /// Sends a byte to the device and waits for the acknowledgement, resending if asked to.
+    ///
+    /// Must be called with the interrupt of the device disabled, otherwise the response is read
+    /// by the interrupt handler.
+    pub fn command(&mut self, device: Device, byte: u8) -> bool {
+        for _ in 0..MAX_RESENDS {
+            unsafe {
+                if !self.send(device, byte) {
+                    return false;
+                }
+
+                match self.read_data() {
+                    Some(ACK) => return true,
+                    Some(RESEND) => continue,
+                    _ => return false,
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Resets the device and waits for the self-test result, see [`Controller::command`].
+    pub fn reset(&mut self, device: Device) -> bool {
+        self.command(device, DEVICE_RESET)
+            && unsafe { self.read_data() } == Some(DEVICE_TEST_PASSED)
+    }
+
+    /// Polls a byte sent by the device after a command, see [`Controller::command`].
+    pub fn receive(&mut self) -> Option<u8> {
+        unsafe { self.read_data() }
+    }
+
+    /// Sends a byte to the device without waiting for the response, which is received by the
+    /// interrupt handler.
+    pub fn send(&mut self, device: Device, byte: u8) -> bool {
+        unsafe {
+            if device == Device::Aux && !self.send_command(CMD_WRITE_AUX) {
+                return false;
+            }
+
+            self.write_data(byte)
+        }
+    }
+
+    /// Reads a pending byte and its source, must be called from the interrupt handlers.
+    pub fn read_interrupt_data(&mut self) -> Option<(Device, u8)> {
+        unsafe {
+            let status = self.command.read();
+
+            if status & STATUS_OUTPUT_FULL == 0 {
+                return None;
+            }
+
+            let device = if status & STATUS_AUX_DATA == 0 {
+                Device::Keyboard
+            } else {
+                Device::Aux
+            };
+
+            Some((device, self.data.read()))
+        }
+    }
+
+    unsafe fn read_config(&mut self) -> Option<u8> {
+        if !self.send_command(CMD_READ_CONFIG) {
+            return None;
+        }
+
+        self.read_data()
+    }
+
+    unsafe fn write_config(&mut self, config: u8) {
+        if self.send_command(CMD_WRITE_CONFIG) {
+            self.write_data(config);
+        }
+    }
+
+    unsafe fn send_command(&mut self, command: u8) -> bool {
+        if !self.wait_input_empty() {
+            return false;
+        }
+
+        self.command.write(command);
+
+        true
+    }
+
+    unsafe fn write_data(&mut self, byte: u8) -> bool {
+        if !self.wait_input_empty() {
+            return false;
+        }
+
+        self.data.write(byte);
+
+        true
+    }
+
+    unsafe fn read_data(&mut self) -> Option<u8> {
+        for _ in 0..MAX_POLLS {
+            if self.command.read() & STATUS_OUTPUT_FULL != 0 {
+                return Some(self.data.read());
+            }
+        }
+
+        None
+    }
+
+    unsafe fn wait_input_empty(&mut self) -> bool {
+        for _ in 0..MAX_POLLS {
+            if self.command.read() & STATUS_INPUT_FULL == 0 {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Drops stale data.
+    unsafe fn flush(&mut self) {
+        for _ in 0..MAX_POLLS {
+            if self.command.read() & STATUS_OUTPUT_FULL == 0 {
+                break;
+            }
+
+            self.data.read();
+        }
+    }
+}
@@ -1,18 +1,55 @@
-#![allow(dead_code)]
+use core::sync::atomic::{AtomicU64, Ordering};
 
 use spin::Mutex;
 use x86_64::instructions::port::Port;
 
+use crate::time::DateTime;
+
 const CMOS_ADDR: u16 = 0x70;
 const CMOS_DATA: u16 = 0x71;
 
+const SECONDS: u8 = 0x00;
+const SECONDS_ALARM: u8 = 0x01;
+const MINUTES: u8 = 0x02;
+const MINUTES_ALARM: u8 = 0x03;
+const HOURS: u8 = 0x04;
+const HOURS_ALARM: u8 = 0x05;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const REGISTER_A: u8 = 0x0A;
+const REGISTER_B: u8 = 0x0B;
+const REGISTER_C: u8 = 0x0C;
+
+const REGISTER_A_UPDATE: u8 = 1 << 7;
+const REGISTER_B_24H: u8 = 1 << 1;
+const REGISTER_B_BINARY: u8 = 1 << 2;
+const REGISTER_B_ALARM_INT: u8 = 1 << 5;
+const REGISTER_B_PERIODIC_INT: u8 = 1 << 6;
+const REGISTER_B_SET: u8 = 1 << 7;
+const REGISTER_C_ALARM: u8 = 1 << 5;
+const REGISTER_C_PERIODIC: u8 = 1 << 6;
+
+/// Alarm register value matching any time.
+const ALARM_ANY: u8 = 0xFF;
+
+const HOUR_PM: u8 = 1 << 7;
+
+/// Must be locked with interrupts disabled, the interrupt handler locks it.
 pub static RTC: Mutex<Rtc> = Mutex::new(Rtc::new(CMOS_ADDR, CMOS_DATA));
 
+static PERIODIC_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Handler of alarm and periodic interrupts, called from the interrupt.
+pub type Handler = fn();
+
 pub struct Rtc {
     addr: Port<u8>,
     data: Port<u8>,
 
     century_reg: Option<u8>,
+    alarm_handler: Option<Handler>,
+    periodic_handler: Option<Handler>,
 }
 
 impl Rtc {
@@ -21,6 +58,8 @@ impl Rtc {
             addr: Port::new(addr),
             data: Port::new(data),
             century_reg: None,
+            alarm_handler: None,
+            periodic_handler: None,
         }
     }
 
@@ -28,7 +67,8 @@ impl Rtc {
         self.century_reg.replace(century_reg);
     }
 
-    pub fn time(&mut self) -> u64 {
+    /// Reads the current date and time, [`DateTime::unix_time`] converts it to Unix time.
+    pub fn time(&mut self) -> DateTime {
         loop {
             unsafe {
                 while self.update_in_progress() {}
@@ -43,72 +83,130 @@ impl Rtc {
         }
     }
 
-    unsafe fn read_unchecked_time(&mut self) -> u64 {
-        let mut second = self.read(0) as usize;
-        let mut minute = self.read(2) as usize;
-        let mut hour = self.read(4) as usize;
-        let mut day = self.read(7) as usize;
-        let mut month = self.read(8) as usize;
-        let mut year = self.read(9) as usize;
-        let mut century = if let Some(reg) = self.century_reg {
-            self.read(reg) as usize
-        } else {
-            20
-        };
+    /// Sets the date and time, the year must be in the range of the century register or 20xx if
+    /// it's not present.
+    #[allow(dead_code, reason = "nothing sets the wall clock yet")]
+    pub fn set_time(&mut self, time: &DateTime) {
+        debug_assert!(time.is_valid(), "invalid date {time}");
+
+        unsafe {
+            let register_b = self.read(REGISTER_B);
+
+            // Updates are stopped while the SET bit is on, so the write is consistent.
+            self.write(REGISTER_B, register_b | REGISTER_B_SET);
+
+            #[allow(clippy::cast_possible_truncation)]
+            let (century, year) = ((time.year / 100) as u8, (time.year % 100) as u8);
 
-        let register_b = self.read(0xB);
-
-        if register_b & 4 != 4 {
-            second = cvt_bcd(second);
-            minute = cvt_bcd(minute);
-            hour = cvt_bcd(hour & 0x7F) | (hour & 0x80);
-            day = cvt_bcd(day);
-            month = cvt_bcd(month);
-            year = cvt_bcd(year);
-            century = if self.century_reg.is_some() {
-                cvt_bcd(century)
-            } else {
-                century
-            };
+            self.write(SECONDS, encode(time.second, register_b));
+            self.write(MINUTES, encode(time.minute, register_b));
+            self.write(HOURS, encode_hour(time.hour, register_b));
+            self.write(DAY, encode(time.day, register_b));
+            self.write(MONTH, encode(time.month, register_b));
+            self.write(YEAR, encode(year, register_b));
+
+            if let Some(reg) = self.century_reg {
+                self.write(reg, encode(century, register_b));
+            }
+
+            self.write(REGISTER_B, register_b & !REGISTER_B_SET);
         }
+    }
+
+    /// Raises the alarm interrupt every day at the time and calls the handler from it, `None`
+    /// fields match any value.
+    #[allow(dead_code, reason = "no alarm user yet")]
+    pub fn set_alarm(
+        &mut self,
+        hour: Option<u8>,
+        minute: Option<u8>,
+        second: Option<u8>,
+        handler: Handler,
+    ) {
+        self.alarm_handler.replace(handler);
+
+        unsafe {
+            let register_b = self.read(REGISTER_B);
+
+            let hour = hour.map_or(ALARM_ANY, |hour| encode_hour(hour, register_b));
+            let minute = minute.map_or(ALARM_ANY, |minute| encode(minute, register_b));
+            let second = second.map_or(ALARM_ANY, |second| encode(second, register_b));
+
+            self.write(SECONDS_ALARM, second);
+            self.write(MINUTES_ALARM, minute);
+            self.write(HOURS_ALARM, hour);
+
+            self.write(REGISTER_B, register_b | REGISTER_B_ALARM_INT);
+        }
+    }
 
-        if register_b & 2 != 2 || hour & 0x80 == 0x80 {
-            hour = ((hour & 0x7F) + 12) % 24;
+    /// Disables the alarm interrupt.
+    #[allow(dead_code, reason = "no alarm user yet")]
+    pub fn clear_alarm(&mut self) {
+        self.alarm_handler.take();
+
+        unsafe {
+            let register_b = self.read(REGISTER_B);
+            self.write(REGISTER_B, register_b & !REGISTER_B_ALARM_INT);
         }
+    }
+
+    /// Raises the periodic interrupt at the power of two rate closest to `hz` from 2 Hz to
+    /// 8192 Hz and calls the handler from it, returns the rate set.
+    #[allow(dead_code, reason = "the periodic interrupt has no user yet")]
+    pub fn set_periodic(&mut self, hz: u32, handler: Option<Handler>) -> u32 {
+        // Rates 3 to 15 divide the 32768 Hz base by 2^(rate - 1).
+        let shift = periodic_shift(hz);
+        let rate = 16 - shift;
 
-        year += century * 100;
+        self.periodic_handler = handler;
 
-        // Unix time from clock
-        let mut secs: u64 = (year as u64 - 1970) * 31_536_000;
-        let mut leap_days = (year as u64 - 1972) / 4 + 1;
+        unsafe {
+            #[allow(clippy::cast_possible_truncation)]
+            let register_a = (self.read(REGISTER_A) & 0xF0) | rate as u8;
+            self.write(REGISTER_A, register_a);
 
-        if year % 4 == 0 && month <= 2 {
-            leap_days -= 1;
+            let register_b = self.read(REGISTER_B);
+            self.write(REGISTER_B, register_b | REGISTER_B_PERIODIC_INT);
         }
 
-        secs += leap_days * 86_400;
-
-        match month {
-            2 => secs += 2_678_400,
-            3 => secs += 5_097_600,
-            4 => secs += 7_776_000,
-            5 => secs += 10_368_000,
-            6 => secs += 13_046_400,
-            7 => secs += 15_638_400,
-            8 => secs += 18_316_800,
-            9 => secs += 20_995_200,
-            10 => secs += 23_587_200,
-            11 => secs += 26_265_600,
-            12 => secs += 28_857_600,
-            _ => (),
+        1 << shift
+    }
+
+    /// Disables the periodic interrupt.
+    #[allow(dead_code, reason = "the periodic interrupt has no user yet")]
+    pub fn stop_periodic(&mut self) {
+        self.periodic_handler.take();
+
+        unsafe {
+            let register_b = self.read(REGISTER_B);
+            self.write(REGISTER_B, register_b & !REGISTER_B_PERIODIC_INT);
         }
+    }
+
+    unsafe fn read_unchecked_time(&mut self) -> DateTime {
+        let register_b = self.read(REGISTER_B);
 
-        secs += (day as u64 - 1) * 86_400;
-        secs += hour as u64 * 3600;
-        secs += minute as u64 * 60;
-        secs += second as u64;
+        let second = decode(self.read(SECONDS), register_b);
+        let minute = decode(self.read(MINUTES), register_b);
+        let hour = decode_hour(self.read(HOURS), register_b);
+        let day = decode(self.read(DAY), register_b);
+        let month = decode(self.read(MONTH), register_b);
+        let year = decode(self.read(YEAR), register_b);
 
-        secs
+        let century = match self.century_reg {
+            Some(reg) => decode(self.read(reg), register_b),
+            None => 20,
+        };
+
+        DateTime {
+            year: u16::from(century) * 100 + u16::from(year),
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
     }
 
     unsafe fn read(&mut self, reg: u8) -> u8 {
@@ -116,11 +214,161 @@ impl Rtc {
         self.data.read()
     }
 
+    unsafe fn write(&mut self, reg: u8, value: u8) {
+        self.addr.write(reg);
+        self.data.write(value);
+    }
+
     unsafe fn update_in_progress(&mut self) -> bool {
-        self.read(0xA) & 80 != 0
+        self.read(REGISTER_A) & REGISTER_A_UPDATE != 0
     }
 }
 
-fn cvt_bcd(value: usize) -> usize {
+/// Acknowledges the interrupt and calls handlers of its sources, must be called from the RTC
+/// interrupt.
+pub fn handle_interrupt() {
+    let (flags, alarm, periodic) = {
+        let mut rtc = RTC.lock();
+
+        // The interrupt is raised again only after register C is read.
+        let flags = unsafe { rtc.read(REGISTER_C) };
+
+        (flags, rtc.alarm_handler, rtc.periodic_handler)
+    };
+
+    if flags & REGISTER_C_PERIODIC != 0 {
+        PERIODIC_TICKS.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(handler) = periodic {
+            handler();
+        }
+    }
+
+    if flags & REGISTER_C_ALARM != 0 {
+        if let Some(handler) = alarm {
+            handler();
+        }
+    }
+}
+
+/// Returns the number of periodic interrupts.
+#[allow(dead_code, reason = "the periodic interrupt has no user yet")]
+pub fn periodic_ticks() -> u64 {
+    PERIODIC_TICKS.load(Ordering::Relaxed)
+}
+
+/// Returns log2 of the power of two rate closest to `hz`, from 2 Hz to 8192 Hz.
+fn periodic_shift(hz: u32) -> u32 {
+    let hz = hz.clamp(2, 8192);
+    let shift = hz.ilog2();
+
+    // Ties round down.
+    if hz - (1 << shift) > (2 << shift) - hz {
+        shift + 1
+    } else {
+        shift
+    }
+}
+
+fn decode(value: u8, register_b: u8) -> u8 {
+    if register_b & REGISTER_B_BINARY == 0 {
+        cvt_bcd(value)
+    } else {
+        value
+    }
+}
+
+fn encode(value: u8, register_b: u8) -> u8 {
+    if register_b & REGISTER_B_BINARY == 0 {
+        ((value / 10) << 4) | (value % 10)
+    } else {
+        value
+    }
+}
+
+/// Decodes the hour to the 24-hour format, the PM flag is set in bit 7 in 12-hour mode.
+fn decode_hour(value: u8, register_b: u8) -> u8 {
+    let hour = decode(value & !HOUR_PM, register_b);
+
+    if register_b & REGISTER_B_24H != 0 {
+        return hour;
+    }
+
+    if value & HOUR_PM == 0 {
+        hour % 12
+    } else {
+        hour % 12 + 12
+    }
+}
+
+fn encode_hour(hour: u8, register_b: u8) -> u8 {
+    if register_b & REGISTER_B_24H != 0 {
+        return encode(hour, register_b);
+    }
+
+    let pm = if hour >= 12 { HOUR_PM } else { 0 };
+
+    match hour % 12 {
+        0 => encode(12, register_b) | pm,
+        hour => encode(hour, register_b) | pm,
+    }
+}
+
+fn cvt_bcd(value: u8) -> u8 {
     (value & 0xF) + ((value / 16) * 10)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BCD_12H: u8 = 0;
+    const BCD_24H: u8 = REGISTER_B_24H;
+    const BINARY_12H: u8 = REGISTER_B_BINARY;
+
+    #[test]
+    fn periodic_rates() {
+        let rate = |hz| 1 << periodic_shift(hz);
+
+        assert_eq!(rate(1000), 1024);
+        assert_eq!(rate(700), 512);
+        assert_eq!(rate(768), 512);
+        assert_eq!(rate(1024), 1024);
+        assert_eq!(rate(0), 2);
+        assert_eq!(rate(u32::MAX), 8192);
+    }
+
+    #[test]
+    fn hour_decoding() {
+        let cases = [
+            (0x12, BCD_12H, 0),
+            (0x01, BCD_12H, 1),
+            (0x11, BCD_12H, 11),
+            (0x92, BCD_12H, 12),
+            (0x81, BCD_12H, 13),
+            (0x91, BCD_12H, 23),
+            (0x23, BCD_24H, 23),
+            (0x8B, BINARY_12H, 23),
+        ];
+
+        for (value, register_b, hour) in cases {
+            assert_eq!(decode_hour(value, register_b), hour, "{value:#x}");
+        }
+    }
+
+    #[test]
+    fn hour_round_trip() {
+        for register_b in [BCD_12H, BCD_24H, BINARY_12H, BINARY_12H | REGISTER_B_24H] {
+            for hour in 0..24 {
+                assert_eq!(decode_hour(encode_hour(hour, register_b), register_b), hour);
+            }
+        }
+    }
+
+    #[test]
+    fn bcd() {
+        assert_eq!(decode(0x59, BCD_24H), 59);
+        assert_eq!(encode(59, BCD_24H), 0x59);
+        assert_eq!(encode(59, BINARY_12H), 59);
+    }
+}
@@ -1,57 +1,428 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
 use spin::Mutex;
 use x86_64::instructions::port::{Port, PortReadOnly, PortWriteOnly};
 
-pub static COM1: Mutex<SerialPort> = Mutex::new(SerialPort::empty(0x3F8));
-pub static COM2: Mutex<SerialPort> = Mutex::new(SerialPort::empty(0x2F8));
+use crate::interrupts;
+use crate::ring_buffer::RingBuffer;
+
+pub static COM1: Mutex<SerialPort> = Mutex::new(SerialPort::empty(0x3F8, &COM1_RX, &COM1_TX));
+pub static COM2: Mutex<SerialPort> = Mutex::new(SerialPort::empty(0x2F8, &COM2_RX, &COM2_TX));
+pub static COM3: Mutex<SerialPort> = Mutex::new(SerialPort::empty(0x3E8, &COM3_RX, &COM3_TX));
+pub static COM4: Mutex<SerialPort> = Mutex::new(SerialPort::empty(0x2E8, &COM4_RX, &COM4_TX));
+
+/// Ports in the order of COM numbers.
+pub static PORTS: [&Mutex<SerialPort>; 4] = [&COM1, &COM2, &COM3, &COM4];
+
+/// Received data of ports, filled by interrupt handlers without locking the ports. COM3 shares
+/// the interrupt with COM1, COM4 with COM2.
+pub static COM1_RX: Receiver = Receiver::new(0x3F8);
+pub static COM2_RX: Receiver = Receiver::new(0x2F8);
+pub static COM3_RX: Receiver = Receiver::new(0x3E8);
+pub static COM4_RX: Receiver = Receiver::new(0x2E8);
+
+/// Data waiting for transmission, drained by interrupt handlers once buffering is enabled.
+pub static COM1_TX: Transmitter = Transmitter::new(0x3F8);
+pub static COM2_TX: Transmitter = Transmitter::new(0x2F8);
+pub static COM3_TX: Transmitter = Transmitter::new(0x3E8);
+pub static COM4_TX: Transmitter = Transmitter::new(0x2E8);
+
+const RX_BUFFER_SIZE: usize = 1024;
+const TX_BUFFER_SIZE: usize = 4096;
+/// Bytes written at once when the transmitter is empty, with FIFO enabled.
+const TX_FIFO_SIZE: usize = 16;
+/// Bounds draining of the receive FIFO, a broken UART may report data forever.
+const MAX_DRAIN: usize = 256;
+
+/// Baud rate with divisor 1.
+const BASE_BAUD: u32 = 115_200;
+
+/// Polls of the line status for the loopback byte, which takes about 87 us at the base baud
+/// rate. Port reads take about 1 us, no clock is needed, the test runs before timers exist.
+const LOOPBACK_POLLS: usize = 1000;
+
+const INT_RX_DATA: u8 = 1 << 0;
+const INT_TX_EMPTY: u8 = 1 << 1;
+const INT_MODEM_STATUS: u8 = 1 << 3;
+
+const FIFO_ENABLE: u8 = 1 << 0;
+const FIFO_CLEAR_RX: u8 = 1 << 1;
+const FIFO_CLEAR_TX: u8 = 1 << 2;
+const FIFO_TRIGGER_14: u8 = 0b11 << 6;
+
+const LINE_CTRL_DLAB: u8 = 1 << 7;
+
+const MODEM_DTR: u8 = 1 << 0;
+const MODEM_RTS: u8 = 1 << 1;
+const MODEM_OUT1: u8 = 1 << 2;
+const MODEM_OUT2: u8 = 1 << 3;
+const MODEM_LOOPBACK: u8 = 1 << 4;
+
+const MODEM_STS_CTS: u8 = 1 << 4;
+
+const LINE_DATA_READY: u8 = 1 << 0;
+const LINE_OVERRUN: u8 = 1 << 1;
+const LINE_PARITY: u8 = 1 << 2;
+const LINE_FRAMING: u8 = 1 << 3;
+const LINE_BREAK: u8 = 1 << 4;
+const LINE_TX_EMPTY: u8 = 1 << 5;
+
+/// Probes all ports and configures present ones with the default configuration.
+pub(super) fn init() {
+    for port in PORTS {
+        port.lock().init(&Config::default());
+    }
+}
+
+/// Switches writes of all ports to the transmit buffer, must be called once interrupts are
+/// enabled.
+pub fn enable_buffering() {
+    for port in PORTS {
+        port.lock().set_buffered(true);
+    }
+}
+
+/// What a buffered write does when the transmit buffer is full.
+#[allow(dead_code, reason = "blocking writes have no user yet")]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Overflow {
+    /// Drops the data and counts it, so the writer never waits.
+    Drop,
+    /// Waits for free space, transmitting from the writer if needed.
+    Block,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Variant {
+    /// No scratch register, no FIFO.
+    Uart8250,
+    /// No FIFO.
+    Uart16450,
+    /// FIFO is present but unusable.
+    Uart16550,
+    /// Working 16-byte FIFO.
+    Uart16550A,
+}
+
+#[allow(dead_code, reason = "only 8N1 is configured for now")]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DataBits {
+    Five,
+    Six,
+    Seven,
+    Eight,
+}
+
+#[allow(dead_code, reason = "only 8N1 is configured for now")]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    Mark,
+    Space,
+}
+
+#[allow(dead_code, reason = "only 8N1 is configured for now")]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StopBits {
+    One,
+    /// One and a half with five data bits.
+    Two,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Config {
+    /// Rounded down to a divisor of 115200.
+    pub baud: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    /// Transmit only while CTS is asserted.
+    pub flow_control: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl Config {
+    /// 38400 baud, 8N1, no flow control.
+    pub const DEFAULT: Self = Self {
+        baud: 38_400,
+        data_bits: DataBits::Eight,
+        parity: Parity::None,
+        stop_bits: StopBits::One,
+        flow_control: false,
+    };
+
+    fn divisor(&self) -> u16 {
+        u16::try_from(BASE_BAUD / self.baud.max(1))
+            .unwrap_or(u16::MAX)
+            .max(1)
+    }
+
+    fn line_ctrl(&self) -> u8 {
+        let data_bits = match self.data_bits {
+            DataBits::Five => 0b00,
+            DataBits::Six => 0b01,
+            DataBits::Seven => 0b10,
+            DataBits::Eight => 0b11,
+        };
+
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 0,
+            StopBits::Two => 1 << 2,
+        };
+
+        let parity = match self.parity {
+            Parity::None => 0b000,
+            Parity::Odd => 0b001,
+            Parity::Even => 0b011,
+            Parity::Mark => 0b101,
+            Parity::Space => 0b111,
+        };
+
+        data_bits | stop_bits | parity << 3
+    }
+}
 
 pub struct SerialPort {
     data: Port<u8>,
-    int_en: PortWriteOnly<u8>,
-    fifo_ctrl: PortWriteOnly<u8>,
-    line_ctrl: PortWriteOnly<u8>,
-    modem_ctrl: PortWriteOnly<u8>,
+    int_en: Port<u8>,
+    // FIFO control on write, interrupt identification on read.
+    fifo_ctrl: Port<u8>,
+    line_ctrl: Port<u8>,
+    modem_ctrl: Port<u8>,
     line_sts: PortReadOnly<u8>,
+    modem_sts: PortReadOnly<u8>,
+    scratch: Port<u8>,
+
+    rx: &'static Receiver,
+    tx: &'static Transmitter,
+    variant: Option<Variant>,
+    config: Config,
+    buffered: bool,
+    overflow: Overflow,
 }
 
 impl SerialPort {
-    const fn empty(base: u16) -> Self {
+    const fn empty(base: u16, rx: &'static Receiver, tx: &'static Transmitter) -> Self {
         Self {
             data: Port::new(base),
-            int_en: PortWriteOnly::new(base + 1),
-            fifo_ctrl: PortWriteOnly::new(base + 2),
-            line_ctrl: PortWriteOnly::new(base + 3),
-            modem_ctrl: PortWriteOnly::new(base + 4),
+            int_en: Port::new(base + 1),
+            fifo_ctrl: Port::new(base + 2),
+            line_ctrl: Port::new(base + 3),
+            modem_ctrl: Port::new(base + 4),
             line_sts: PortReadOnly::new(base + 5),
+            modem_sts: PortReadOnly::new(base + 6),
+            scratch: Port::new(base + 7),
+            rx,
+            tx,
+            variant: None,
+            config: Config::DEFAULT,
+            buffered: false,
+            overflow: Overflow::Drop,
         }
     }
 
-    pub(super) fn init(&mut self) {
+    /// Configures the port and enables receive interrupts, returns `None` if the port fails the
+    /// loopback self-test, so it's not present.
+    pub fn init(&mut self, config: &Config) -> Option<Variant> {
+        self.set_buffered(false);
+
+        self.variant = None;
+        self.rx.set_present(false);
+        self.tx.set_fifo_size(0);
+
         unsafe {
-            self.int_en.write(0x00); //     Disable interrupts
-            self.line_ctrl.write(0x80); //  Enable DLAB (set baud rate divisor)
-            self.data.write(0x03); //       Set divisor to 3 (lo byte) 38400 baud
-            self.int_en.write(0x00); //     Set divisor to 3 (hi byte)
-            self.line_ctrl.write(0x03); //  8 bits, no parity, one stop bit
-            self.fifo_ctrl.write(0xC7); //  Enable FIFO, clear them, with 14-byte threshold
-            self.modem_ctrl.write(0x0B); // IRQs enabled, RTS/DSR set
-            self.int_en.write(0x01); //     Enable interrupts
+            self.int_en.write(0);
+
+            // The fastest rate keeps the self-test short whatever the configured one is.
+            self.set_line(&Config {
+                baud: BASE_BAUD,
+                ..*config
+            });
+
+            if !self.self_test() {
+                self.modem_ctrl.write(0);
+                return None;
+            }
+
+            self.set_line(config);
+
+            let variant = self.detect_variant();
+
+            let fifo = if variant == Variant::Uart16550A {
+                FIFO_ENABLE | FIFO_CLEAR_RX | FIFO_CLEAR_TX | FIFO_TRIGGER_14
+            } else {
+                0
+            };
+
+            self.fifo_ctrl.write(fifo);
+            // OUT2 gates the interrupt line on PC compatibles.
+            self.modem_ctrl.write(MODEM_DTR | MODEM_RTS | MODEM_OUT2);
+            self.int_en.write(INT_RX_DATA);
+
+            self.variant = Some(variant);
+            self.tx
+                .set_fifo_size(if fifo == 0 { 1 } else { TX_FIFO_SIZE });
+        }
+
+        self.config = *config;
+        self.rx.set_present(true);
+        self.tx.set_flow_control(config.flow_control);
+
+        self.variant
+    }
+
+    /// Returns the detected UART, `None` if the port is not present.
+    pub fn variant(&self) -> Option<Variant> {
+        self.variant
+    }
+
+    #[allow(dead_code, reason = "the line settings are fixed at boot for now")]
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Changes the line settings of the present port.
+    #[allow(dead_code, reason = "the line settings are fixed at boot for now")]
+    pub fn set_config(&mut self, config: &Config) {
+        if self.variant.is_none() {
+            return;
+        }
+
+        unsafe { self.set_line(config) };
+        self.config = *config;
+        self.tx.set_flow_control(config.flow_control);
+    }
+
+    /// Sets the policy of buffered writes when the transmit buffer is full.
+    #[allow(dead_code, reason = "the line settings are fixed at boot for now")]
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+    }
+
+    /// Switches between buffered writes drained by the interrupt and synchronous writes, pending
+    /// data is flushed when buffering is disabled.
+    pub fn set_buffered(&mut self, buffered: bool) {
+        if self.variant.is_none() || self.buffered == buffered {
+            return;
+        }
+
+        if !buffered {
+            self.tx.flush();
+        }
+
+        self.buffered = buffered;
+    }
+
+    /// Switches to synchronous writes on panic, other CPUs may have been stopped while draining
+    /// the transmit buffer.
+    ///
+    /// Flow control is turned off, a disconnected peer must not hang the crash report.
+    ///
+    /// # Safety
+    ///
+    /// Other CPUs must be stopped.
+    pub unsafe fn force_sync(&mut self) {
+        if self.tx.drain.is_locked() {
+            self.tx.drain.force_unlock();
         }
+
+        self.config.flow_control = false;
+        self.tx.set_flow_control(false);
+
+        self.set_buffered(false);
     }
 
     pub fn write(&mut self, buf: &[u8]) {
-        buf.iter().for_each(|b| self.write_byte(*b));
+        if self.variant.is_none() {
+            return;
+        }
+
+        if self.buffered {
+            self.tx.write(buf, self.overflow);
+        } else {
+            buf.iter().for_each(|b| self.write_byte(*b));
+        }
     }
 
     fn write_byte(&mut self, byte: u8) {
         unsafe {
-            while !self.transit_empty() {}
+            while !self.transit_empty() || !self.clear_to_send() {}
             self.data.write(byte);
         }
     }
 
+    unsafe fn set_line(&mut self, config: &Config) {
+        let [low, high] = config.divisor().to_le_bytes();
+
+        self.line_ctrl.write(LINE_CTRL_DLAB);
+        self.data.write(low);
+        self.int_en.write(high);
+        self.line_ctrl.write(config.line_ctrl());
+    }
+
+    /// Sends a byte to itself in loopback mode, an absent port reads all ones.
+    unsafe fn self_test(&mut self) -> bool {
+        const PATTERN: u8 = 0xAE;
+
+        self.fifo_ctrl.write(0);
+        self.modem_ctrl
+            .write(MODEM_LOOPBACK | MODEM_OUT2 | MODEM_OUT1 | MODEM_RTS);
+
+        // Stale data may be waiting in the receiver.
+        for _ in 0..MAX_DRAIN {
+            if self.line_sts.read() & LINE_DATA_READY == 0 {
+                break;
+            }
+
+            self.data.read();
+        }
+
+        self.data.write(PATTERN);
+
+        for _ in 0..LOOPBACK_POLLS {
+            if self.line_sts.read() & LINE_DATA_READY != 0 {
+                return self.data.read() == PATTERN;
+            }
+        }
+
+        false
+    }
+
+    /// Detects the UART by bits 6-7 of the interrupt identification with FIFO enabled, which
+    /// are zero without FIFO, and by the scratch register missing on 8250.
+    unsafe fn detect_variant(&mut self) -> Variant {
+        self.fifo_ctrl.write(FIFO_ENABLE);
+
+        match self.fifo_ctrl.read() >> 6 {
+            0b11 => Variant::Uart16550A,
+            0b10 => Variant::Uart16550,
+            _ => {
+                self.scratch.write(0x5A);
+
+                if self.scratch.read() == 0x5A {
+                    Variant::Uart16450
+                } else {
+                    Variant::Uart8250
+                }
+            }
+        }
+    }
+
     #[inline]
     unsafe fn transit_empty(&mut self) -> bool {
-        self.line_sts.read() & 0x20 != 0
+        self.line_sts.read() & LINE_TX_EMPTY != 0
+    }
+
+    #[inline]
+    unsafe fn clear_to_send(&mut self) -> bool {
+        !self.config.flow_control || self.modem_sts.read() & MODEM_STS_CTS != 0
     }
 }
 
@@ -62,3 +433,314 @@ impl core::fmt::Write for SerialPort {
         Ok(())
     }
 }
+
+pub struct Receiver {
+    data: u16,
+    line_sts: u16,
+    present: AtomicBool,
+    buffer: RingBuffer<u8, RX_BUFFER_SIZE>,
+    // Serializes consumers of the buffer.
+    reader: Mutex<()>,
+    overruns: AtomicU64,
+    parity_errors: AtomicU64,
+    framing_errors: AtomicU64,
+    breaks: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Receive errors reported by the line status register since boot.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct LineErrors {
+    /// Bytes lost by the UART because its FIFO was full.
+    pub overruns: u64,
+    pub parity_errors: u64,
+    pub framing_errors: u64,
+    pub breaks: u64,
+    /// Bytes lost because the receive buffer was full.
+    pub dropped: u64,
+}
+
+impl Receiver {
+    const fn new(base: u16) -> Self {
+        Self {
+            data: base,
+            line_sts: base + 5,
+            present: AtomicBool::new(false),
+            buffer: RingBuffer::new(),
+            reader: Mutex::new(()),
+            overruns: AtomicU64::new(0),
+            parity_errors: AtomicU64::new(0),
+            framing_errors: AtomicU64::new(0),
+            breaks: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Drains the UART receive FIFO to the buffer, must be called from the port interrupt.
+    ///
+    /// Doesn't log, the logger may hold the port lock on this CPU.
+    pub fn handle_interrupt(&self) {
+        if !self.present.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut data = PortReadOnly::<u8>::new(self.data);
+        let mut line_sts = PortReadOnly::<u8>::new(self.line_sts);
+
+        for _ in 0..MAX_DRAIN {
+            let status = unsafe { line_sts.read() };
+
+            count_if(&self.overruns, status & LINE_OVERRUN);
+            count_if(&self.parity_errors, status & LINE_PARITY);
+            count_if(&self.framing_errors, status & LINE_FRAMING);
+            count_if(&self.breaks, status & LINE_BREAK);
+
+            if status & LINE_DATA_READY == 0 {
+                break;
+            }
+
+            let byte = unsafe { data.read() };
+
+            // Bytes with parity or framing errors are garbage.
+            if status & (LINE_PARITY | LINE_FRAMING | LINE_BREAK) != 0 {
+                continue;
+            }
+
+            if self.buffer.push(byte).is_err() {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn set_present(&self, present: bool) {
+        self.present.store(present, Ordering::SeqCst);
+    }
+
+    /// Returns the next received byte, `None` if there is no one.
+    #[allow(dead_code, reason = "nothing reads from the serial ports yet")]
+    pub fn try_read(&self) -> Option<u8> {
+        let _reader = self.reader.lock();
+
+        self.buffer.pop()
+    }
+
+    /// Reads received bytes to the buffer without waiting, returns the number of bytes read.
+    #[allow(dead_code, reason = "nothing reads from the serial ports yet")]
+    pub fn read_available(&self, buf: &mut [u8]) -> usize {
+        let _reader = self.reader.lock();
+
+        buf.iter_mut()
+            .map_while(|slot| self.buffer.pop().map(|byte| *slot = byte))
+            .count()
+    }
+
+    /// Waits for the next received byte, must be called with interrupts enabled.
+    #[allow(dead_code, reason = "nothing reads from the serial ports yet")]
+    pub fn read(&self) -> u8 {
+        loop {
+            // The byte may arrive between the check and halt, so check with interrupts disabled.
+            interrupts::disable();
+
+            if let Some(byte) = self.try_read() {
+                interrupts::enable();
+                return byte;
+            }
+
+            interrupts::enable_and_hlt();
+        }
+    }
+
+    #[allow(dead_code, reason = "nothing reads from the serial ports yet")]
+    pub fn errors(&self) -> LineErrors {
+        LineErrors {
+            overruns: self.overruns.load(Ordering::Relaxed),
+            parity_errors: self.parity_errors.load(Ordering::Relaxed),
+            framing_errors: self.framing_errors.load(Ordering::Relaxed),
+            breaks: self.breaks.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub struct Transmitter {
+    data: u16,
+    int_en: u16,
+    line_sts: u16,
+    modem_sts: u16,
+    // Bytes written when the transmitter is empty, zero if the port is not present.
+    fifo_size: AtomicUsize,
+    flow_control: AtomicBool,
+    buffer: RingBuffer<u8, TX_BUFFER_SIZE>,
+    // Serializes consumers of the buffer, the interrupt handler and writers waiting for space.
+    drain: Mutex<()>,
+    dropped: AtomicU64,
+}
+
+impl Transmitter {
+    const fn new(base: u16) -> Self {
+        Self {
+            data: base,
+            int_en: base + 1,
+            line_sts: base + 5,
+            modem_sts: base + 6,
+            fifo_size: AtomicUsize::new(0),
+            flow_control: AtomicBool::new(false),
+            buffer: RingBuffer::new(),
+            drain: Mutex::new(()),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Refills the UART from the buffer, must be called from the port interrupt.
+    pub fn handle_interrupt(&self) {
+        if self.fifo_size.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+
+        // Someone else is draining, the interrupt is raised again once the UART is empty.
+        if let Some(_drain) = self.drain.try_lock() {
+            self.refill();
+        }
+    }
+
+    /// Returns the number of bytes dropped because the buffer was full.
+    #[allow(dead_code, reason = "transmit drops are not reported yet")]
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn set_fifo_size(&self, size: usize) {
+        self.fifo_size.store(size, Ordering::SeqCst);
+    }
+
+    fn set_flow_control(&self, flow_control: bool) {
+        self.flow_control.store(flow_control, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if the peer is ready to receive, always without flow control.
+    unsafe fn clear_to_send(&self) -> bool {
+        !self.flow_control.load(Ordering::Relaxed)
+            || PortReadOnly::<u8>::new(self.modem_sts).read() & MODEM_STS_CTS != 0
+    }
+
+    /// Must be called with the port locked, so there is a single producer.
+    fn write(&self, buf: &[u8], overflow: Overflow) {
+        for byte in buf {
+            while self.buffer.push(*byte).is_err() {
+                if overflow == Overflow::Drop {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    break;
+                }
+
+                // Interrupts may be disabled on the CPU receiving them, so transmit from here.
+                if let Some(_drain) = self.drain.try_lock() {
+                    self.refill();
+                }
+
+                core::hint::spin_loop();
+            }
+        }
+
+        // The interrupt is raised right away if the UART is already empty.
+        unsafe { PortWriteOnly::new(self.int_en).write(INT_RX_DATA | INT_TX_EMPTY) };
+    }
+
+    /// Transmits all buffered data synchronously and stops the interrupt.
+    fn flush(&self) {
+        let _drain = self.drain.lock();
+
+        let mut data = PortWriteOnly::<u8>::new(self.data);
+        let mut line_sts = PortReadOnly::<u8>::new(self.line_sts);
+
+        unsafe {
+            PortWriteOnly::new(self.int_en).write(INT_RX_DATA);
+
+            while let Some(byte) = self.buffer.pop() {
+                while line_sts.read() & LINE_TX_EMPTY == 0 || !self.clear_to_send() {}
+                data.write(byte);
+            }
+        }
+    }
+
+    /// Writes the next chunk if the UART is empty, must be called with the drain lock held.
+    fn refill(&self) {
+        let mut data = PortWriteOnly::<u8>::new(self.data);
+        let mut int_en = PortWriteOnly::<u8>::new(self.int_en);
+        let mut line_sts = PortReadOnly::<u8>::new(self.line_sts);
+
+        unsafe {
+            if line_sts.read() & LINE_TX_EMPTY == 0 {
+                return;
+            }
+
+            // Reading the modem status acknowledges its interrupt, which resumes transmission
+            // once CTS is asserted.
+            if !self.clear_to_send() {
+                int_en.write(INT_RX_DATA | INT_MODEM_STATUS);
+                return;
+            }
+
+            for _ in 0..self.fifo_size.load(Ordering::Relaxed) {
+                let Some(byte) = self.buffer.pop() else {
+                    break;
+                };
+
+                data.write(byte);
+            }
+
+            if !self.buffer.is_empty() {
+                // Transmission may resume from the modem status interrupt.
+                int_en.write(INT_RX_DATA | INT_TX_EMPTY);
+                return;
+            }
+
+            // A writer may push after the check and enable the interrupt before it's disabled
+            // here, so check again after disabling.
+            int_en.write(INT_RX_DATA);
+
+            if !self.buffer.is_empty() {
+                int_en.write(INT_RX_DATA | INT_TX_EMPTY);
+            }
+        }
+    }
+}
+
+fn count_if(counter: &AtomicU64, flag: u8) {
+    if flag != 0 {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_registers() {
+        assert_eq!(Config::DEFAULT.line_ctrl(), 0x03);
+        assert_eq!(Config::DEFAULT.divisor(), 3);
+
+        let config = Config {
+            baud: 9600,
+            data_bits: DataBits::Seven,
+            parity: Parity::Even,
+            stop_bits: StopBits::Two,
+            flow_control: true,
+        };
+
+        assert_eq!(config.line_ctrl(), 0x1E);
+        assert_eq!(config.divisor(), 12);
+
+        let divisor = |baud| {
+            Config {
+                baud,
+                ..Config::DEFAULT
+            }
+            .divisor()
+        };
+
+        assert_eq!(divisor(115_200), 1);
+        assert_eq!(divisor(1_000_000), 1);
+        assert_eq!(divisor(1), u16::MAX);
+    }
+}
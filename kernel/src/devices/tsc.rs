@@ -0,0 +1,154 @@
+//! Time stamp counter, the main clock source when it's invariant.
+//!
+//! APs measure the offset of their counter from the BSP one at startup: the AP requests the BSP
+//! counter and assumes it was read in the middle of the round trip. Reads are corrected by the
+//! offset, so the counter is the same on all CPUs.
+
+use core::arch::x86_64::_rdtsc;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+
+use raw_cpuid::CpuId;
+
+use crate::devices::cpu;
+use crate::time::{self, ClockSource, USEC_PER_SEC};
+
+pub static TSC: Tsc = Tsc::empty();
+
+/// Duration of the calibration against the reference clock.
+const CALIBRATION_US: u64 = 50_000;
+
+/// Round trips of the offset measurement, the shortest one is used.
+const SYNC_ROUNDS: usize = 16;
+
+const SYNC_IDLE: u8 = 0;
+const SYNC_REQUEST: u8 = 1;
+const SYNC_REPLY: u8 = 2;
+
+/// State of the offset measurement handshake.
+static SYNC_STATE: AtomicU8 = AtomicU8::new(SYNC_IDLE);
+/// BSP counter sent in reply.
+static SYNC_VALUE: AtomicU64 = AtomicU64::new(0);
+
+/// Added to the counter of the current CPU, wraps around for negative offsets.
+#[thread_local]
+static mut OFFSET: u64 = 0;
+
+pub struct Tsc {
+    frequency: AtomicU64,
+    invariant: AtomicBool,
+}
+
+impl Tsc {
+    const fn empty() -> Self {
+        Self {
+            frequency: AtomicU64::new(0),
+            invariant: AtomicBool::new(false),
+        }
+    }
+
+    /// Measures the frequency, must be called once on the BSP with interrupts disabled.
+    pub(super) fn init(&self) {
+        let invariant = CpuId::new()
+            .get_advanced_power_mgmt_info()
+            .is_some_and(|info| info.has_invariant_tsc());
+
+        let frequency = match cpu::tsc_frequency() {
+            Some(frequency) => frequency,
+            None => {
+                let start = unsafe { _rdtsc() };
+                let reference = time::calibration_wait(CALIBRATION_US);
+                let elapsed = unsafe { _rdtsc() } - start;
+
+                if let Some(reference) = reference {
+                    log::debug!("TSC calibrated against {reference}");
+
+                    elapsed * USEC_PER_SEC / CALIBRATION_US
+                } else {
+                    log::warn!("No reference clock, TSC frequency is unknown");
+                    0
+                }
+            }
+        };
+
+        self.frequency.store(frequency, Ordering::SeqCst);
+        self.invariant.store(invariant, Ordering::SeqCst);
+
+        if !invariant {
+            log::warn!("TSC is not invariant, it may drift with frequency changes");
+        }
+
+        log::debug!("TSC frequency: {frequency} Hz");
+    }
+
+    /// Returns `true` if the counter runs at a constant rate in all power states.
+    pub fn is_invariant(&self) -> bool {
+        self.invariant.load(Ordering::Relaxed)
+    }
+
+    /// Serves a pending offset measurement of an AP, must be polled by the BSP while the AP starts.
+    pub(super) fn sync_bsp(&self) {
+        if SYNC_STATE.load(Ordering::Acquire) == SYNC_REQUEST {
+            SYNC_VALUE.store(unsafe { _rdtsc() }, Ordering::Relaxed);
+            SYNC_STATE.store(SYNC_REPLY, Ordering::Release);
+        }
+    }
+
+    /// Measures the offset from the BSP counter, must be called on the AP while the BSP polls
+    /// [`Tsc::sync_bsp`].
+    pub(super) fn sync_ap(&self) {
+        let mut best_round_trip = u64::MAX;
+        let mut offset = 0;
+
+        for _ in 0..SYNC_ROUNDS {
+            let start = unsafe { _rdtsc() };
+            SYNC_STATE.store(SYNC_REQUEST, Ordering::Release);
+
+            while SYNC_STATE.load(Ordering::Acquire) != SYNC_REPLY {
+                core::hint::spin_loop();
+            }
+
+            let end = unsafe { _rdtsc() };
+            let bsp = SYNC_VALUE.load(Ordering::Relaxed);
+            SYNC_STATE.store(SYNC_IDLE, Ordering::Release);
+
+            let round_trip = end.wrapping_sub(start);
+
+            if round_trip < best_round_trip {
+                best_round_trip = round_trip;
+                offset = bsp.wrapping_sub(start.wrapping_add(round_trip / 2));
+            }
+        }
+
+        unsafe { OFFSET = offset };
+
+        #[allow(clippy::cast_possible_wrap)]
+        let offset = offset as i64;
+        log::debug!(
+            "CPU{} TSC offset: {offset} cycles, round trip: {best_round_trip} cycles",
+            cpu::current_id()
+        );
+    }
+}
+
+impl ClockSource for Tsc {
+    fn name(&self) -> &'static str {
+        "TSC"
+    }
+
+    fn frequency(&self) -> u64 {
+        self.frequency.load(Ordering::Relaxed)
+    }
+
+    fn mask(&self) -> u64 {
+        u64::MAX
+    }
+
+    fn read(&self) -> u64 {
+        unsafe { _rdtsc().wrapping_add(OFFSET) }
+    }
+
+    /// The counter of an AP is not synchronized to the BSP without the offset.
+    fn read_global(&self) -> u64 {
+        unsafe { _rdtsc() }
+    }
+}
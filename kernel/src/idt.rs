@@ -3,7 +3,7 @@ use x86_64::{PrivilegeLevel, VirtAddr};
 
 use crate::memory::KERNEL_FRAME_ALLOCATOR;
 
-use crate::interrupts::{exception, irq};
+use crate::interrupts::{exception, irq, vector};
 use crate::prelude::*;
 
 ////////////////////////////////////////////////////////////////////////////////////////////////////
@@ -16,19 +16,15 @@ pub fn init_early() {
     log::trace!("init early IDT");
 
     unsafe {
-        EARLY_IDT
-            .double_fault
-            .set_handler_fn(exception::double_fault);
-        EARLY_IDT
-            .segment_not_present
-            .set_handler_fn(exception::segment_not_present);
-        EARLY_IDT
-            .general_protection_fault
-            .set_handler_fn(exception::general_protection_fault);
-        EARLY_IDT.page_fault.set_handler_fn(exception::page_fault);
-        EARLY_IDT
-            .alignment_check
-            .set_handler_fn(exception::alignment_check);
+        for vector in [
+            exception::DOUBLE_FAULT,
+            exception::SEGMENT_NOT_PRESENT,
+            exception::GENERAL_PROTECTION_FAULT,
+            exception::PAGE_FAULT,
+            exception::ALIGNMENT_CHECK,
+        ] {
+            EARLY_IDT[usize::from(vector)].set_handler_addr(exception::entry(vector));
+        }
 
         EARLY_IDT.load();
     }
@@ -70,77 +66,63 @@ unsafe fn init_generic(is_bsp: bool, phys_offset: VirtAddr, idt: &mut InterruptD
     super::gdt::TSS.interrupt_stack_table[usize::from(KERNEL_BACKUP_STACK_INDEX)] = stack_end;
 
     // Set up exceptions
-    idt.divide_error.set_handler_fn(exception::divide_error);
-    idt.debug.set_handler_fn(exception::debug);
+    idt.divide_error
+        .set_handler_addr(exception::entry(exception::DIVIDE_ERROR));
+    idt.debug
+        .set_handler_addr(exception::entry(exception::DEBUG));
     idt.non_maskable_interrupt
-        .set_handler_fn(exception::nmi)
+        .set_handler_addr(exception::entry(exception::NMI))
         .set_stack_index(KERNEL_BACKUP_STACK_INDEX);
     idt.breakpoint
-        .set_handler_fn(exception::breakpoint)
+        .set_handler_addr(exception::entry(exception::BREAKPOINT))
         .set_present(true)
         .set_privilege_level(PrivilegeLevel::Ring3);
 
-    idt.overflow.set_handler_fn(exception::overflow);
+    idt.overflow
+        .set_handler_addr(exception::entry(exception::OVERFLOW));
     idt.bound_range_exceeded
-        .set_handler_fn(exception::bound_range_exceeded);
-    idt.invalid_opcode.set_handler_fn(exception::invalid_opcode);
+        .set_handler_addr(exception::entry(exception::BOUND_RANGE_EXCEEDED));
+    idt.invalid_opcode
+        .set_handler_addr(exception::entry(exception::INVALID_OPCODE));
     idt.device_not_available
-        .set_handler_fn(exception::device_not_available);
+        .set_handler_addr(exception::entry(exception::DEVICE_NOT_AVAILABLE));
     idt.double_fault
-        .set_handler_fn(exception::double_fault)
+        .set_handler_addr(exception::entry(exception::DOUBLE_FAULT))
         .set_stack_index(KERNEL_BACKUP_STACK_INDEX);
 
-    idt.invalid_tss.set_handler_fn(exception::invalid_tss);
+    idt.invalid_tss
+        .set_handler_addr(exception::entry(exception::INVALID_TSS));
     idt.segment_not_present
-        .set_handler_fn(exception::segment_not_present);
+        .set_handler_addr(exception::entry(exception::SEGMENT_NOT_PRESENT));
     idt.stack_segment_fault
-        .set_handler_fn(exception::stack_segment_fault);
+        .set_handler_addr(exception::entry(exception::STACK_SEGMENT_FAULT));
     idt.general_protection_fault
-        .set_handler_fn(exception::general_protection_fault);
-    idt.page_fault.set_handler_fn(exception::page_fault);
+        .set_handler_addr(exception::entry(exception::GENERAL_PROTECTION_FAULT));
+    idt.page_fault
+        .set_handler_addr(exception::entry(exception::PAGE_FAULT));
     idt.x87_floating_point
-        .set_handler_fn(exception::x87_floating_point);
+        .set_handler_addr(exception::entry(exception::X87_FLOATING_POINT));
     idt.alignment_check
-        .set_handler_fn(exception::alignment_check);
+        .set_handler_addr(exception::entry(exception::ALIGNMENT_CHECK));
     idt.machine_check
-        .set_handler_fn(exception::machine_check)
+        .set_handler_addr(exception::entry(exception::MACHINE_CHECK))
         .set_stack_index(KERNEL_BACKUP_STACK_INDEX);
     idt.simd_floating_point
-        .set_handler_fn(exception::simd_floating_point);
-    idt.virtualization.set_handler_fn(exception::virtualization);
+        .set_handler_addr(exception::entry(exception::SIMD_FLOATING_POINT));
+    idt.virtualization
+        .set_handler_addr(exception::entry(exception::VIRTUALIZATION));
     idt.vmm_communication_exception
-        .set_handler_fn(exception::vmm_communication_exception);
+        .set_handler_addr(exception::entry(exception::VMM_COMMUNICATION_EXCEPTION));
     idt.security_exception
-        .set_handler_fn(exception::security_exception);
+        .set_handler_addr(exception::entry(exception::SECURITY_EXCEPTION));
 
     if is_bsp {
-        idt[32].set_handler_fn(irq::pit_stack);
-        idt[33].set_handler_fn(irq::keyboard);
-        idt[34].set_handler_fn(irq::cascade);
-        idt[35].set_handler_fn(irq::com2);
-        idt[36].set_handler_fn(irq::com1);
-        idt[37].set_handler_fn(irq::lpt2);
-        idt[38].set_handler_fn(irq::floppy);
-        idt[39].set_handler_fn(irq::lpt1);
-        idt[40].set_handler_fn(irq::rtc);
-        idt[41].set_handler_fn(irq::pci1);
-        idt[42].set_handler_fn(irq::pci2);
-        idt[43].set_handler_fn(irq::pci3);
-        idt[44].set_handler_fn(irq::mouse);
-        idt[45].set_handler_fn(irq::fpu);
-        idt[46].set_handler_fn(irq::ata1);
-        idt[47].set_handler_fn(irq::ata2);
-        idt[48].set_handler_fn(irq::lapic_timer);
-        idt[49].set_handler_fn(irq::lapic_error);
-    } else {
-        idt[49].set_handler_fn(irq::lapic_error);
+        irq::init();
     }
 
-    // Fill empty IDT entries with a default unimplemented interrupt handler.
-    for entry in idt.slice_mut(32..=255) {
-        if entry.handler_addr().as_u64() == 0 {
-            entry.set_handler_fn(irq::unimplemented);
-        }
+    // Interrupts are counted and dispatched to handlers registered by vector.
+    for vector in vector::IRQ_BASE..=u8::MAX {
+        idt[usize::from(vector)].set_handler_fn(irq::dispatch_handler(vector));
     }
 
     unsafe { IDT.load() }
@@ -0,0 +1,423 @@
+//! Input devices and their events, delivered to every [`Reader`] in the order of arrival.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::interrupts;
+
+const QUEUE_SIZE: usize = 256;
+
+/// Pushed by interrupt handlers, so readers lock it with interrupts disabled.
+static QUEUE: Mutex<Queue<QUEUE_SIZE>> = Mutex::new(Queue::new());
+static DEVICES: Mutex<Vec<DeviceInfo>> = Mutex::new(Vec::new());
+static NEXT_DEVICE_ID: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct DeviceId(u32);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DeviceKind {
+    Keyboard,
+    Mouse,
+    /// Absolute pointing device, e.g. a tablet or a virtual machine pointer.
+    #[allow(dead_code, reason = "no tablet driver yet")]
+    Tablet(Axes),
+}
+
+/// Range of absolute positions reported by the device, from zero up to the maximum.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Axes {
+    pub max_x: u16,
+    pub max_y: u16,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct DeviceInfo {
+    pub id: DeviceId,
+    pub name: &'static str,
+    pub kind: DeviceKind,
+}
+
+/// Event with the device it came from.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct InputEvent {
+    pub device: DeviceId,
+    pub event: Event,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Event {
+    Key(KeyEvent),
+    /// Relative pointer motion, `dy` grows downwards like screen coordinates.
+    Motion {
+        dx: i16,
+        dy: i16,
+    },
+    /// Absolute pointer position within the [`Axes`] of the device, `y` grows downwards.
+    #[allow(dead_code, reason = "no tablet driver yet")]
+    Position {
+        x: u16,
+        y: u16,
+    },
+    Button {
+        button: Button,
+        pressed: bool,
+    },
+    /// Wheel steps, positive when scrolled up.
+    Wheel(i8),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Button {
+    Left,
+    Right,
+    Middle,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct KeyEvent {
+    pub key: KeyCode,
+    pub pressed: bool,
+    /// Modifiers and lock keys after the event.
+    pub modifiers: Modifiers,
+    /// Character produced by the key press with the layout, if any.
+    pub ch: Option<char>,
+}
+
+/// Position independent key codes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum KeyCode {
+    Escape,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    PrintScreen,
+    ScrollLock,
+    Pause,
+
+    Grave,
+    Digit1,
+    Digit2,
+    Digit3,
+    Digit4,
+    Digit5,
+    Digit6,
+    Digit7,
+    Digit8,
+    Digit9,
+    Digit0,
+    Minus,
+    Equals,
+    Backspace,
+
+    Tab,
+    Q,
+    W,
+    E,
+    R,
+    T,
+    Y,
+    U,
+    I,
+    O,
+    P,
+    LeftBracket,
+    RightBracket,
+    Backslash,
+
+    CapsLock,
+    A,
+    S,
+    D,
+    F,
+    G,
+    H,
+    J,
+    K,
+    L,
+    Semicolon,
+    Quote,
+    Enter,
+
+    LeftShift,
+    Z,
+    X,
+    C,
+    V,
+    B,
+    N,
+    M,
+    Comma,
+    Period,
+    Slash,
+    RightShift,
+
+    LeftCtrl,
+    LeftGui,
+    LeftAlt,
+    Space,
+    RightAlt,
+    RightGui,
+    Menu,
+    RightCtrl,
+
+    Insert,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Up,
+    Down,
+    Left,
+    Right,
+
+    NumLock,
+    KeypadSlash,
+    KeypadStar,
+    KeypadMinus,
+    KeypadPlus,
+    KeypadEnter,
+    KeypadPeriod,
+    Keypad0,
+    Keypad1,
+    Keypad2,
+    Keypad3,
+    Keypad4,
+    Keypad5,
+    Keypad6,
+    Keypad7,
+    Keypad8,
+    Keypad9,
+}
+
+/// Held modifier keys and active lock keys.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Modifiers(u16);
+
+impl Modifiers {
+    pub const LEFT_SHIFT: Self = Self(1 << 0);
+    pub const RIGHT_SHIFT: Self = Self(1 << 1);
+    pub const LEFT_CTRL: Self = Self(1 << 2);
+    pub const RIGHT_CTRL: Self = Self(1 << 3);
+    pub const LEFT_ALT: Self = Self(1 << 4);
+    pub const RIGHT_ALT: Self = Self(1 << 5);
+    pub const LEFT_GUI: Self = Self(1 << 6);
+    pub const RIGHT_GUI: Self = Self(1 << 7);
+    pub const CAPS_LOCK: Self = Self(1 << 8);
+    pub const NUM_LOCK: Self = Self(1 << 9);
+    pub const SCROLL_LOCK: Self = Self(1 << 10);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    #[allow(dead_code, reason = "part of the modifiers API without a user yet")]
+    pub const fn bits(self) -> u16 {
+        self.0
+    }
+
+    /// Returns `true` if any of the flags is set.
+    pub const fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn set(&mut self, other: Self, value: bool) {
+        if value {
+            self.0 |= other.0;
+        } else {
+            self.0 &= !other.0;
+        }
+    }
+
+    pub fn toggle(&mut self, other: Self) {
+        self.0 ^= other.0;
+    }
+
+    pub const fn shift(self) -> bool {
+        self.intersects(Self(Self::LEFT_SHIFT.0 | Self::RIGHT_SHIFT.0))
+    }
+
+    pub const fn ctrl(self) -> bool {
+        self.intersects(Self(Self::LEFT_CTRL.0 | Self::RIGHT_CTRL.0))
+    }
+
+    #[allow(dead_code, reason = "part of the modifiers API without a user yet")]
+    pub const fn alt(self) -> bool {
+        self.intersects(Self(Self::LEFT_ALT.0 | Self::RIGHT_ALT.0))
+    }
+
+    #[allow(dead_code, reason = "part of the modifiers API without a user yet")]
+    pub const fn gui(self) -> bool {
+        self.intersects(Self(Self::LEFT_GUI.0 | Self::RIGHT_GUI.0))
+    }
+}
+
+/// Adds a device, its events are pushed with the returned id.
+pub fn register(name: &'static str, kind: DeviceKind) -> DeviceId {
+    let id = DeviceId(NEXT_DEVICE_ID.fetch_add(1, Ordering::Relaxed));
+
+    DEVICES.lock().push(DeviceInfo { id, name, kind });
+
+    log::debug!("Input device {}: {name} ({kind:?})", id.0);
+
+    id
+}
+
+/// Removes the device, events it pushed before stay queued.
+#[allow(dead_code, reason = "no input device is hot-pluggable yet")]
+pub fn unregister(id: DeviceId) {
+    DEVICES.lock().retain(|device| device.id != id);
+}
+
+#[allow(dead_code, reason = "nothing lists input devices yet")]
+pub fn devices() -> Vec<DeviceInfo> {
+    DEVICES.lock().clone()
+}
+
+/// Queues the event of the device, overwriting the oldest one if the queue is full.
+pub fn push(device: DeviceId, event: Event) {
+    without_interrupts(|| QUEUE.lock().push(InputEvent { device, event }));
+}
+
+/// Returns a reader of events pushed from now on.
+pub fn reader() -> Reader {
+    let cursor = without_interrupts(|| QUEUE.lock().next);
+
+    Reader { cursor, lost: 0 }
+}
+
+/// Independent consumer of the queue, each reader receives every event.
+pub struct Reader {
+    // Sequence number of the next event to read.
+    cursor: u64,
+    lost: u64,
+}
+
+impl Reader {
+    /// Returns the oldest unread event, `None` if there is no one.
+    pub fn try_read(&mut self) -> Option<InputEvent> {
+        let (event, lost) = without_interrupts(|| QUEUE.lock().read(&mut self.cursor));
+
+        self.lost += lost;
+
+        event
+    }
+
+    /// Waits for the next event, must be called with interrupts enabled.
+    pub fn read(&mut self) -> InputEvent {
+        loop {
+            // The event may arrive between the check and halt, so check with interrupts disabled.
+            interrupts::disable();
+
+            if let Some(event) = self.try_read() {
+                interrupts::enable();
+                return event;
+            }
+
+            interrupts::enable_and_hlt();
+        }
+    }
+
+    /// Returns the number of events overwritten before this reader got to them.
+    #[allow(dead_code, reason = "the console reader does not report lost events")]
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+}
+
+/// Bounded queue of the last `N` events, read through cursors of readers.
+struct Queue<const N: usize> {
+    events: [Option<InputEvent>; N],
+    // Sequence number of the next event, its slot is the number modulo `N`.
+    next: u64,
+}
+
+impl<const N: usize> Queue<N> {
+    const fn new() -> Self {
+        Self {
+            events: [None; N],
+            next: 0,
+        }
+    }
+
+    fn push(&mut self, event: InputEvent) {
+        self.events[self.slot(self.next)] = Some(event);
+        self.next += 1;
+    }
+
+    /// Returns the event at the cursor and advances it, with the number of events skipped because
+    /// they were overwritten.
+    fn read(&self, cursor: &mut u64) -> (Option<InputEvent>, u64) {
+        let oldest = self.next.saturating_sub(N as u64);
+        let lost = oldest.saturating_sub(*cursor);
+        *cursor += lost;
+
+        if *cursor == self.next {
+            return (None, lost);
+        }
+
+        let event = self.events[self.slot(*cursor)];
+        *cursor += 1;
+
+        (event, lost)
+    }
+
+    fn slot(&self, sequence: u64) -> usize {
+        (sequence % N as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(n: i8) -> InputEvent {
+        InputEvent {
+            device: DeviceId(0),
+            event: Event::Wheel(n),
+        }
+    }
+
+    #[test]
+    fn readers_receive_every_event() {
+        let mut queue = Queue::<4>::new();
+        let mut first = 0;
+
+        queue.push(event(1));
+        let mut second = queue.next;
+        queue.push(event(2));
+
+        assert_eq!(queue.read(&mut first), (Some(event(1)), 0));
+        assert_eq!(queue.read(&mut first), (Some(event(2)), 0));
+        assert_eq!(queue.read(&mut first), (None, 0));
+
+        assert_eq!(queue.read(&mut second), (Some(event(2)), 0));
+        assert_eq!(queue.read(&mut second), (None, 0));
+    }
+
+    #[test]
+    fn slow_reader_skips_overwritten_events() {
+        let mut queue = Queue::<4>::new();
+        let mut cursor = 0;
+
+        for n in 0..6 {
+            queue.push(event(n));
+        }
+
+        assert_eq!(queue.read(&mut cursor), (Some(event(2)), 2));
+        assert_eq!(queue.read(&mut cursor), (Some(event(3)), 0));
+    }
+}
@@ -1,177 +1,481 @@
-use x86_64::registers::control::Cr2;
-use x86_64::structures::idt::{InterruptStackFrame, PageFaultErrorCode};
+use core::arch::naked_asm;
+use core::fmt;
 
-// TODO: on exception kill current process
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::registers::model_specific::{FsBase, KernelGsBase};
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::{PageTable, PageTableFlags};
+use x86_64::VirtAddr;
 
-/// # Panics
-///
-/// Will panic if an exception is received.
-pub extern "x86-interrupt" fn divide_error(frame: InterruptStackFrame) {
-    panic!("\nEXCEPTION: DIVIDE ERROR\n{:#X?}", frame);
-}
+use crate::devices::{cpu, mca};
+use crate::{panic, symbols};
 
-pub extern "x86-interrupt" fn debug(frame: InterruptStackFrame) {
-    log::warn!("\nEXCEPTION: DEBUG EXCEPTION\n{:#X?}", frame);
-    // don't halt here, this isn't a fatal/permanent failure, just a brief pause.
-}
+use super::{stats, watchdog};
+
+pub const DIVIDE_ERROR: u8 = 0;
+pub const DEBUG: u8 = 1;
+pub const NMI: u8 = 2;
+pub const BREAKPOINT: u8 = 3;
+pub const OVERFLOW: u8 = 4;
+pub const BOUND_RANGE_EXCEEDED: u8 = 5;
+pub const INVALID_OPCODE: u8 = 6;
+pub const DEVICE_NOT_AVAILABLE: u8 = 7;
+pub const DOUBLE_FAULT: u8 = 8;
+pub const INVALID_TSS: u8 = 10;
+pub const SEGMENT_NOT_PRESENT: u8 = 11;
+pub const STACK_SEGMENT_FAULT: u8 = 12;
+pub const GENERAL_PROTECTION_FAULT: u8 = 13;
+pub const PAGE_FAULT: u8 = 14;
+pub const X87_FLOATING_POINT: u8 = 16;
+pub const ALIGNMENT_CHECK: u8 = 17;
+pub const MACHINE_CHECK: u8 = 18;
+pub const SIMD_FLOATING_POINT: u8 = 19;
+pub const VIRTUALIZATION: u8 = 20;
+pub const VMM_COMMUNICATION_EXCEPTION: u8 = 29;
+pub const SECURITY_EXCEPTION: u8 = 30;
 
+/// Returns the entry point of the exception vector.
+///
 /// # Panics
 ///
-/// Will panic if an exception is received.
-pub extern "x86-interrupt" fn nmi(frame: InterruptStackFrame) {
-    panic!("\nEXCEPTION: NON-MASKABLE INTERRUPT\n{:#X?}", frame);
-}
+/// Will panic if the vector is not an exception handled by the kernel.
+pub fn entry(vector: u8) -> VirtAddr {
+    let entry: extern "C" fn() = match vector {
+        DIVIDE_ERROR => stub::<DIVIDE_ERROR>,
+        DEBUG => stub::<DEBUG>,
+        NMI => stub::<NMI>,
+        BREAKPOINT => stub::<BREAKPOINT>,
+        OVERFLOW => stub::<OVERFLOW>,
+        BOUND_RANGE_EXCEEDED => stub::<BOUND_RANGE_EXCEEDED>,
+        INVALID_OPCODE => stub::<INVALID_OPCODE>,
+        DEVICE_NOT_AVAILABLE => stub::<DEVICE_NOT_AVAILABLE>,
+        DOUBLE_FAULT => stub_with_error_code::<DOUBLE_FAULT>,
+        INVALID_TSS => stub_with_error_code::<INVALID_TSS>,
+        SEGMENT_NOT_PRESENT => stub_with_error_code::<SEGMENT_NOT_PRESENT>,
+        STACK_SEGMENT_FAULT => stub_with_error_code::<STACK_SEGMENT_FAULT>,
+        GENERAL_PROTECTION_FAULT => stub_with_error_code::<GENERAL_PROTECTION_FAULT>,
+        PAGE_FAULT => stub_with_error_code::<PAGE_FAULT>,
+        X87_FLOATING_POINT => stub::<X87_FLOATING_POINT>,
+        ALIGNMENT_CHECK => stub_with_error_code::<ALIGNMENT_CHECK>,
+        MACHINE_CHECK => stub::<MACHINE_CHECK>,
+        SIMD_FLOATING_POINT => stub::<SIMD_FLOATING_POINT>,
+        VIRTUALIZATION => stub::<VIRTUALIZATION>,
+        VMM_COMMUNICATION_EXCEPTION => stub_with_error_code::<VMM_COMMUNICATION_EXCEPTION>,
+        SECURITY_EXCEPTION => stub_with_error_code::<SECURITY_EXCEPTION>,
+        vector => panic!("no entry for exception vector {vector}"),
+    };
 
-pub extern "x86-interrupt" fn breakpoint(frame: InterruptStackFrame) {
-    log::warn!("\nEXCEPTION: BREAKPOINT\n{:#X?}", frame);
-    // don't halt here, this isn't a fatal/permanent failure, just a brief pause.
+    VirtAddr::from_ptr(entry as *const ())
 }
 
-/// # Panics
-///
-/// Will panic if an exception is received.
-pub extern "x86-interrupt" fn overflow(frame: InterruptStackFrame) {
-    panic!("\nEXCEPTION: OVERFLOW\n{:#X?}", frame);
+fn name(vector: u8) -> &'static str {
+    match vector {
+        DIVIDE_ERROR => "DIVIDE ERROR",
+        DEBUG => "DEBUG EXCEPTION",
+        NMI => "NON-MASKABLE INTERRUPT",
+        BREAKPOINT => "BREAKPOINT",
+        OVERFLOW => "OVERFLOW",
+        BOUND_RANGE_EXCEEDED => "BOUND RANGE EXCEEDED",
+        INVALID_OPCODE => "INVALID OPCODE",
+        DEVICE_NOT_AVAILABLE => "DEVICE NOT AVAILABLE",
+        DOUBLE_FAULT => "DOUBLE FAULT",
+        INVALID_TSS => "INVALID TSS",
+        SEGMENT_NOT_PRESENT => "SEGMENT NOT PRESENT",
+        STACK_SEGMENT_FAULT => "STACK SEGMENT FAULT",
+        GENERAL_PROTECTION_FAULT => "GENERAL PROTECTION FAULT",
+        PAGE_FAULT => "PAGE FAULT",
+        X87_FLOATING_POINT => "x87 FLOATING POINT",
+        ALIGNMENT_CHECK => "ALIGNMENT CHECK",
+        MACHINE_CHECK => "MACHINE CHECK",
+        SIMD_FLOATING_POINT => "SIMD FLOATING POINT",
+        VIRTUALIZATION => "VIRTUALIZATION",
+        VMM_COMMUNICATION_EXCEPTION => "VMM COMMUNICATION EXCEPTION",
+        SECURITY_EXCEPTION => "SECURITY EXCEPTION",
+        _ => "UNKNOWN",
+    }
 }
 
-/// # Panics
-///
-/// Will panic if an exception is received.
-pub extern "x86-interrupt" fn bound_range_exceeded(frame: InterruptStackFrame) {
-    panic!("\nEXCEPTION: BOUND RANGE EXCEEDED\n{:#X?}", frame);
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// Common entry
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// General purpose registers saved by the common exception entry.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Registers {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
 }
 
-/// # Panics
-///
-/// Will panic if an exception is received.
-pub extern "x86-interrupt" fn invalid_opcode(frame: InterruptStackFrame) {
-    panic!("\nEXCEPTION: INVALID OPCODE\n{:#X?}", frame);
+/// Exception state saved on the stack, the layout must match [`common`].
+#[repr(C)]
+#[derive(Debug)]
+pub struct ExceptionFrame {
+    pub regs: Registers,
+    pub vector: u64,
+    pub error_code: u64,
+
+    // Pushed by CPU.
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
 }
 
-/// # Panics
-///
-/// Will panic if an exception is received.
-pub extern "x86-interrupt" fn device_not_available(frame: InterruptStackFrame) {
-    panic!("\nEXCEPTION: DEVICE NOT AVAILABLE\n{:#X?}", frame);
+impl ExceptionFrame {
+    /// Returns `true` if the exception was raised in user mode, by the RPL of the saved CS.
+    pub fn is_user_mode(&self) -> bool {
+        self.cs & 0b11 == 3
+    }
 }
 
-/// # Panics
-///
-/// Will panic if an exception is received.
-pub extern "x86-interrupt" fn double_fault(frame: InterruptStackFrame, err: u64) -> ! {
-    panic!(
-        "\nEXCEPTION: DOUBLE FAULT\n{:#X?}\nError code: {:#b}",
-        frame, err
-    );
+/// Fault raised by user mode code, the exit reason of the task once there are tasks.
+#[derive(Debug, Copy, Clone)]
+pub struct Fault {
+    pub vector: u8,
+    pub error_code: u64,
+    pub rip: u64,
+    /// Accessed address of a page fault.
+    pub addr: Option<u64>,
 }
 
-/// # Panics
-///
-/// Will panic if an exception is received.
-pub extern "x86-interrupt" fn invalid_tss(frame: InterruptStackFrame, err: u64) {
-    panic!(
-        "\nEXCEPTION: INVALID TSS\n{:#X?}\nError code: {:#b}",
-        frame, err
-    );
+impl Fault {
+    /// Returns the fault if it's caused by the user mode code.
+    ///
+    /// Double faults, machine checks and NMIs are not caused by the task, so they stay fatal.
+    pub fn from_frame(frame: &ExceptionFrame) -> Option<Self> {
+        #[allow(clippy::cast_possible_truncation)]
+        let vector = frame.vector as u8;
+
+        if !frame.is_user_mode() || matches!(vector, NMI | DOUBLE_FAULT | MACHINE_CHECK) {
+            return None;
+        }
+
+        Some(Self {
+            vector,
+            error_code: frame.error_code,
+            rip: frame.rip,
+            addr: (vector == PAGE_FAULT).then(Cr2::read_raw),
+        })
+    }
 }
 
-/// # Panics
-///
-/// Will panic if an exception is received.
-pub extern "x86-interrupt" fn segment_not_present(frame: InterruptStackFrame, err: u64) {
-    panic!(
-        "\nEXCEPTION: SEGMENT NOT PRESENT\n{:#X?}\nError code: {:#b}",
-        frame, err
-    );
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {:#x}, error code {:#x}",
+            name(self.vector),
+            self.rip,
+            self.error_code
+        )?;
+
+        if let Some(addr) = self.addr {
+            write!(f, ", address {addr:#x}")?;
+        }
+
+        Ok(())
+    }
 }
 
-/// # Panics
-///
-/// Will panic if an exception is received.
-pub extern "x86-interrupt" fn stack_segment_fault(frame: InterruptStackFrame, err: u64) {
-    panic!(
-        "\nEXCEPTION: STACK SEGMENT FAULT\n{:#X?}\nError code: {:#b}",
-        frame, err
+/// Entry of exceptions without error code, pushes zero instead to keep the frame layout.
+#[unsafe(naked)]
+extern "C" fn stub<const VECTOR: u8>() {
+    naked_asm!(
+        "push 0",
+        "push {vector}",
+        "jmp {common}",
+        vector = const VECTOR,
+        common = sym common,
     );
 }
 
-/// # Panics
-///
-/// Will panic if an exception is received.
-pub extern "x86-interrupt" fn general_protection_fault(frame: InterruptStackFrame, err: u64) {
-    panic!(
-        "\nEXCEPTION: GENERAL PROTECTION FAULT\n{:#X?}\nError code: {:#b}",
-        frame, err
+#[unsafe(naked)]
+extern "C" fn stub_with_error_code<const VECTOR: u8>() {
+    naked_asm!(
+        "push {vector}",
+        "jmp {common}",
+        vector = const VECTOR,
+        common = sym common,
     );
 }
 
-/// # Panics
-///
-/// Will panic if an exception is received.
-pub extern "x86-interrupt" fn page_fault(frame: InterruptStackFrame, err: PageFaultErrorCode) {
-    panic!(
-        "\nEXCEPTION: PAGE FAULT while accessing {:#x}\n\
-        error code: {:?}\n{:#X?}",
-        Cr2::read_raw(),
-        err,
-        frame
+/// Saves general purpose registers and calls [`dispatch`] with [`ExceptionFrame`].
+#[unsafe(naked)]
+extern "C" fn common() {
+    naked_asm!(
+        "push rax",
+        "push rbx",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push rbp",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        // Stack is 16 bytes aligned here: CPU frame, error code, vector and 15 registers.
+        "mov rdi, rsp",
+        "cld",
+        "call {dispatch}",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rbp",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rbx",
+        "pop rax",
+        // Skip vector and error code.
+        "add rsp, 16",
+        "iretq",
+        dispatch = sym dispatch,
     );
 }
 
-/// # Panics
-///
-/// Will panic if an exception is received.
-pub extern "x86-interrupt" fn x87_floating_point(frame: InterruptStackFrame) {
-    panic!("\nEXCEPTION: x87 FLOATING POINT\n{:#X?}", frame);
+/// Switches to the kernel TLS if the exception was raised in user mode and handles it.
+extern "C" fn dispatch(frame: &mut ExceptionFrame) {
+    // Nothing may touch TLS before the kernel FS base is restored.
+    let user_fs_base = frame.is_user_mode().then(|| {
+        let user_fs_base = FsBase::read();
+        FsBase::write(KernelGsBase::read());
+        user_fs_base
+    });
+
+    handle(frame);
+
+    if let Some(user_fs_base) = user_fs_base {
+        FsBase::write(user_fs_base);
+    }
 }
 
 /// # Panics
 ///
-/// Will panic if an exception is received.
-pub extern "x86-interrupt" fn alignment_check(frame: InterruptStackFrame, err: u64) {
-    panic!(
-        "\nEXCEPTION: ALIGNMENT CHECK\n{:#X?}\nError code: {:#b}",
-        frame, err
-    );
+/// Will panic if a fatal exception is received.
+fn handle(frame: &mut ExceptionFrame) {
+    #[allow(clippy::cast_possible_truncation)]
+    let vector = frame.vector as u8;
+
+    // Counters are per-CPU, so exceptions before TLS is set up are not counted.
+    if cpu::try_current_id().is_some() {
+        stats::count(vector);
+    }
+
+    match vector {
+        DEBUG | BREAKPOINT => {
+            log::warn!("\nEXCEPTION: {}\n{}", name(vector), frame);
+            // don't halt here, this isn't a fatal/permanent failure, just a brief pause.
+        }
+        // Another CPU panicked and stops the others.
+        NMI if panic::is_panicking() => panic::stop_current(),
+        NMI if watchdog::handle_nmi(frame) => (),
+        MACHINE_CHECK => {
+            let check = mca::handle();
+
+            if check.is_fatal() {
+                panic::set_exception_frame(frame);
+
+                panic!("\nEXCEPTION: {}\n{check}\n{frame}", name(vector));
+            }
+        }
+        _ => {
+            // The panic handler prints the backtrace of the exception frame.
+            panic::set_exception_frame(frame);
+
+            // There are no tasks to terminate yet, so faults of user mode code are fatal too.
+            if let Some(fault) = Fault::from_frame(frame) {
+                panic!("\nUSER MODE FAULT: {fault}\n{frame}");
+            }
+
+            panic!(
+                "\nEXCEPTION: {}\n{}{}",
+                name(vector),
+                ErrorCode(frame),
+                frame
+            )
+        }
+    }
 }
 
-/// # Panics
-///
-/// Will panic if an exception is received.
-pub extern "x86-interrupt" fn machine_check(frame: InterruptStackFrame) -> ! {
-    panic!("\nEXCEPTION: MACHINE CHECK\n{:#X?}", frame);
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// Formatting
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+impl fmt::Display for ExceptionFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "RIP: {}", symbols::Address(self.rip))?;
+        writeln!(
+            f,
+            "RSP: {:#018x} RFLAGS: {:#018x} CS: {:#06x} SS: {:#06x}",
+            self.rsp, self.rflags, self.cs, self.ss
+        )?;
+
+        write!(f, "{}", self.regs)?;
+
+        writeln!(
+            f,
+            "CR0: {:#018x} CR2: {:#018x} CR3: {:#018x} CR4: {:#018x}",
+            Cr0::read_raw(),
+            Cr2::read_raw(),
+            Cr3::read().0.start_address().as_u64(),
+            Cr4::read_raw()
+        )
+    }
 }
 
-/// # Panics
-///
-/// Will panic if an exception is received.
-pub extern "x86-interrupt" fn simd_floating_point(frame: InterruptStackFrame) {
-    panic!("\nEXCEPTION: SIMD FLOATING POINT\n{:#X?}", frame);
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let r = self;
+
+        writeln!(
+            f,
+            "RAX: {:#018x} RBX: {:#018x} RCX: {:#018x} RDX: {:#018x}",
+            r.rax, r.rbx, r.rcx, r.rdx
+        )?;
+        writeln!(
+            f,
+            "RSI: {:#018x} RDI: {:#018x} RBP: {:#018x} R8:  {:#018x}",
+            r.rsi, r.rdi, r.rbp, r.r8
+        )?;
+        writeln!(
+            f,
+            "R9:  {:#018x} R10: {:#018x} R11: {:#018x} R12: {:#018x}",
+            r.r9, r.r10, r.r11, r.r12
+        )?;
+        writeln!(
+            f,
+            "R13: {:#018x} R14: {:#018x} R15: {:#018x}",
+            r.r13, r.r14, r.r15
+        )
+    }
 }
 
-/// # Panics
-///
-/// Will panic if an exception is received.
-pub extern "x86-interrupt" fn virtualization(frame: InterruptStackFrame) {
-    panic!("\nEXCEPTION: VIRTUALIZATION\n{:#X?}", frame);
+/// Decoded error code of the exception.
+struct ErrorCode<'a>(&'a ExceptionFrame);
+
+impl fmt::Display for ErrorCode<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let err = self.0.error_code;
+
+        #[allow(clippy::cast_possible_truncation)]
+        match self.0.vector as u8 {
+            INVALID_TSS | SEGMENT_NOT_PRESENT | STACK_SEGMENT_FAULT | GENERAL_PROTECTION_FAULT => {
+                writeln!(f, "Error code: {}", SelectorErrorCode(err))
+            }
+            PAGE_FAULT => {
+                let addr = Cr2::read_raw();
+
+                writeln!(f, "Accessed address: {addr:#x}")?;
+                writeln!(
+                    f,
+                    "Error code: {:?}",
+                    PageFaultErrorCode::from_bits_truncate(err)
+                )?;
+                write!(f, "{}", PageWalk(VirtAddr::new_truncate(addr)))
+            }
+            DOUBLE_FAULT | ALIGNMENT_CHECK | VMM_COMMUNICATION_EXCEPTION | SECURITY_EXCEPTION => {
+                writeln!(f, "Error code: {err:#b}")
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
-/// # Panics
-///
-/// Will panic if an exception is received.
-pub extern "x86-interrupt" fn vmm_communication_exception(frame: InterruptStackFrame, err: u64) {
-    panic!(
-        "\nEXCEPTION: VMM COMMUNICATION EXCEPTION\n{:#X?}\nError code: {:#b}",
-        frame, err
-    );
+/// Segment selector error code.
+struct SelectorErrorCode(u64);
+
+impl fmt::Display for SelectorErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let err = self.0;
+
+        if err == 0 {
+            return write!(f, "0");
+        }
+
+        let table = match (err >> 1) & 0b11 {
+            0b00 => "GDT",
+            0b10 => "LDT",
+            _ => "IDT",
+        };
+
+        write!(
+            f,
+            "{:#x} (external: {}, table: {}, index: {})",
+            err,
+            err & 1 != 0,
+            table,
+            (err >> 3) & 0x1FFF
+        )
+    }
 }
 
-/// # Panics
-///
-/// Will panic if an exception is received.
-pub extern "x86-interrupt" fn security_exception(frame: InterruptStackFrame, err: u64) {
-    panic!(
-        "\nEXCEPTION: SECURITY EXCEPTION\n{:#X?}\nError code: {:#b}",
-        frame, err
-    );
+/// Page table entries of every level for the address.
+struct PageWalk(VirtAddr);
+
+impl fmt::Display for PageWalk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(phys_offset) = crate::PHYS_OFFSET.get().copied() else {
+            return writeln!(f, "Page walk unavailable");
+        };
+
+        let addr = self.0;
+        let indexes = [
+            addr.p4_index(),
+            addr.p3_index(),
+            addr.p2_index(),
+            addr.p1_index(),
+        ];
+
+        let mut table_addr = Cr3::read().0.start_address();
+
+        for (level, index) in (1..=4).rev().zip(indexes) {
+            let table = VirtAddr::new(phys_offset + table_addr.as_u64());
+            let table = unsafe { &*table.as_ptr::<PageTable>() };
+            let entry = &table[index];
+
+            writeln!(
+                f,
+                "P{level}[{:>3}]: addr: {:#x} flags: {:?}",
+                u16::from(index),
+                entry.addr(),
+                entry.flags()
+            )?;
+
+            if !entry.flags().contains(PageTableFlags::PRESENT)
+                || entry.flags().contains(PageTableFlags::HUGE_PAGE)
+            {
+                break;
+            }
+
+            table_addr = entry.addr();
+        }
+
+        Ok(())
+    }
 }
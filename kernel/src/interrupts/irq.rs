@@ -1,88 +1,174 @@
-use x86_64::instructions::port::PortReadOnly;
-use x86_64::structures::idt::InterruptStackFrame;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use x86_64::structures::idt::{HandlerFunc, InterruptStackFrame};
+
+use crate::devices::ps2::{self, Device};
+use crate::devices::{cpu, keyboard, lapic_timer, local_apic, mca, mouse, rtc, serial};
+
+use super::{eoi, stats, vector, watchdog};
+
+/// Builds a table of `handler` instances for every vector, indexed by `[vector >> 4][vector & 0xF]`.
+macro_rules! vector_table {
+    ($handler:ident) => {
+        vector_table!(@rows $handler; 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15)
+    };
+    (@rows $handler:ident; $($row:literal)*) => {
+        [$(vector_table!(@row $handler; $row)),*]
+    };
+    (@row $handler:ident; $row:literal) => {
+        [
+            $handler::<{ $row * 16 }>,
+            $handler::<{ $row * 16 + 1 }>,
+            $handler::<{ $row * 16 + 2 }>,
+            $handler::<{ $row * 16 + 3 }>,
+            $handler::<{ $row * 16 + 4 }>,
+            $handler::<{ $row * 16 + 5 }>,
+            $handler::<{ $row * 16 + 6 }>,
+            $handler::<{ $row * 16 + 7 }>,
+            $handler::<{ $row * 16 + 8 }>,
+            $handler::<{ $row * 16 + 9 }>,
+            $handler::<{ $row * 16 + 10 }>,
+            $handler::<{ $row * 16 + 11 }>,
+            $handler::<{ $row * 16 + 12 }>,
+            $handler::<{ $row * 16 + 13 }>,
+            $handler::<{ $row * 16 + 14 }>,
+            $handler::<{ $row * 16 + 15 }>,
+        ]
+    };
+}
+
+/// Handler of a dynamically allocated vector, called before end-of-interrupt.
+pub type Handler = fn(vector: u8);
 
-use super::eoi;
+static DISPATCH: [[HandlerFunc; 16]; 16] = vector_table!(dispatch);
+static HANDLERS: [AtomicPtr<()>; 256] = [const { AtomicPtr::new(ptr::null_mut()) }; 256];
 
-pub extern "x86-interrupt" fn pit_stack(_stack: InterruptStackFrame) {
-    eoi();
-}
-
-pub extern "x86-interrupt" fn keyboard(_stack: InterruptStackFrame) {
-    let _: u8 = unsafe { PortReadOnly::new(0x60).read() };
-
-    log::debug!("keyboard interrupt!");
-
-    eoi();
-}
-
-pub extern "x86-interrupt" fn cascade(_stack: InterruptStackFrame) {
-    eoi();
-}
-
-pub extern "x86-interrupt" fn com2(_stack: InterruptStackFrame) {
-    eoi();
-}
-
-pub extern "x86-interrupt" fn com1(_stack: InterruptStackFrame) {
-    eoi();
-}
+/// Registers the handler called by [`dispatch`] for the vector.
+pub fn register(vector: u8, handler: Handler) {
+    HANDLERS[usize::from(vector)].store(handler as *mut (), Ordering::SeqCst);
+}
 
-pub extern "x86-interrupt" fn lpt2(_stack: InterruptStackFrame) {
-    eoi();
+/// Removes the handler registered for the vector.
+pub fn unregister(vector: u8) {
+    HANDLERS[usize::from(vector)].store(ptr::null_mut(), Ordering::SeqCst);
+}
+
+fn handler(vector: u8) -> Option<Handler> {
+    let handler = HANDLERS[usize::from(vector)].load(Ordering::SeqCst);
+
+    // Safety: only `Handler` pointers are stored in `HANDLERS`.
+    (!handler.is_null()).then(|| unsafe { core::mem::transmute::<*mut (), Handler>(handler) })
 }
 
-pub extern "x86-interrupt" fn floppy(_stack: InterruptStackFrame) {
-    eoi();
+/// Registers handlers of legacy IRQs and local APIC vectors, must be called once before IDTs are
+/// loaded.
+pub fn init() {
+    for vector in [
+        vector::PIT,
+        vector::CASCADE,
+        vector::LPT2,
+        vector::FLOPPY,
+        vector::LPT1,
+        vector::PCI1,
+        vector::PCI2,
+        vector::PCI3,
+        vector::FPU,
+        vector::ATA1,
+        vector::ATA2,
+    ] {
+        register(vector, ignore);
+    }
+
+    register(vector::KEYBOARD, ps2);
+    register(vector::MOUSE, ps2);
+    register(vector::COM1, com1);
+    register(vector::COM2, com2);
+    register(vector::RTC, rtc);
+    register(vector::LAPIC_TIMER, lapic_timer);
+    register(vector::LAPIC_ERROR, lapic_error);
+}
+
+/// Acknowledges interrupts of devices without a driver.
+fn ignore(_vector: u8) {}
+
+/// Passes the byte read from the PS/2 controller to the driver of its device, the status tells the
+/// source regardless of the interrupt line.
+fn ps2(_vector: u8) {
+    let data = ps2::CONTROLLER.lock().read_interrupt_data();
+
+    match data {
+        Some((Device::Keyboard, byte)) => keyboard::handle_byte(byte),
+        Some((Device::Aux, byte)) => mouse::handle_byte(byte),
+        None => (),
+    }
+}
+
+fn com1(_vector: u8) {
+    serial::COM1_RX.handle_interrupt();
+    serial::COM1_TX.handle_interrupt();
+    serial::COM3_RX.handle_interrupt();
+    serial::COM3_TX.handle_interrupt();
+}
+
+fn com2(_vector: u8) {
+    serial::COM2_RX.handle_interrupt();
+    serial::COM2_TX.handle_interrupt();
+    serial::COM4_RX.handle_interrupt();
+    serial::COM4_TX.handle_interrupt();
+}
+
+fn rtc(_vector: u8) {
+    rtc::handle_interrupt();
 }
 
-pub extern "x86-interrupt" fn lpt1(_stack: InterruptStackFrame) {
-    eoi();
+fn lapic_timer(_vector: u8) {
+    lapic_timer::handle_interrupt();
+    mca::poll();
+    watchdog::tick();
 }
 
-pub extern "x86-interrupt" fn rtc(_stack: InterruptStackFrame) {
-    eoi();
-}
-
-pub extern "x86-interrupt" fn pci1(_stack: InterruptStackFrame) {
-    eoi();
-}
+fn lapic_error(_vector: u8) {
+    let errors = local_apic::LOCAL_APIC.read_errors();
 
-pub extern "x86-interrupt" fn pci2(_stack: InterruptStackFrame) {
-    eoi();
+    log::error!("CPU{} local APIC error: {errors:?}", cpu::current_id());
 }
 
-pub extern "x86-interrupt" fn pci3(_stack: InterruptStackFrame) {
-    eoi();
+/// Returns the [`dispatch`] handler instance for the vector.
+pub fn dispatch_handler(vector: u8) -> HandlerFunc {
+    DISPATCH[usize::from(vector >> 4)][usize::from(vector & 0xF)]
 }
 
-pub extern "x86-interrupt" fn mouse(_stack: InterruptStackFrame) {
-    let _: u8 = unsafe { PortReadOnly::new(0x60).read() };
+/// Counts the interrupt and calls the handler registered for the vector, unknown vectors are
+/// reported.
+pub extern "x86-interrupt" fn dispatch<const VECTOR: u8>(_stack: InterruptStackFrame) {
+    stats::count(VECTOR);
 
-    log::debug!("mouse interrupt!");
+    // Spurious interrupts are not in service, so they must not be acknowledged.
+    if VECTOR == vector::SPURIOUS {
+        return;
+    }
 
-    eoi();
-}
+    if let Some(handler) = handler(VECTOR) {
+        handler(VECTOR);
+        eoi();
 
-pub extern "x86-interrupt" fn fpu(_stack: InterruptStackFrame) {
-    eoi();
-}
+        // Expired timers run with interrupts enabled, so after end-of-interrupt.
+        if VECTOR == vector::LAPIC_TIMER {
+            crate::timer::run_expired();
+        }
 
-pub extern "x86-interrupt" fn ata1(_stack: InterruptStackFrame) {
-    eoi();
-}
+        return;
+    }
 
-pub extern "x86-interrupt" fn ata2(_stack: InterruptStackFrame) {
-    eoi();
-}
-
-pub extern "x86-interrupt" fn lapic_timer(_stack: InterruptStackFrame) {
-    eoi();
-}
-
-pub extern "x86-interrupt" fn lapic_error(_stack: InterruptStackFrame) {
-    eoi();
-}
+    log::warn!(
+        "CPU{} received unknown interrupt vector {VECTOR}",
+        cpu::current_id()
+    );
 
-pub extern "x86-interrupt" fn unimplemented(_stack: InterruptStackFrame) {
-    eoi();
+    // Don't acknowledge vector that is not in service, otherwise EOI would be applied to another
+    // interrupt with lower priority.
+    if local_apic::LOCAL_APIC.is_in_service(VECTOR) {
+        eoi();
+    }
 }
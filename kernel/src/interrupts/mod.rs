@@ -2,6 +2,9 @@ use crate::devices::local_apic;
 
 pub mod exception;
 pub mod irq;
+pub mod stats;
+pub mod vector;
+pub mod watchdog;
 
 #[inline]
 fn eoi() {
@@ -14,8 +17,23 @@ pub fn hlt() {
     x86_64::instructions::hlt();
 }
 
+/// Enables interrupts and halts the CPU until the next interrupt arrives.
+///
+/// Interrupts are enabled after the next instruction, so an interrupt can't slip in between a check
+/// done with interrupts disabled and halt.
+#[inline]
+pub fn enable_and_hlt() {
+    x86_64::instructions::interrupts::enable_and_hlt();
+}
+
 /// Enable interrupts.
 #[inline]
 pub fn enable() {
     x86_64::instructions::interrupts::enable();
 }
+
+/// Disable interrupts.
+#[inline]
+pub fn disable() {
+    x86_64::instructions::interrupts::disable();
+}
@@ -0,0 +1,72 @@
+//! Per-CPU interrupt counters.
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::devices::{cpu, io_apic, serial};
+use crate::prelude::*;
+
+use super::vector;
+
+static COUNTERS: [[AtomicU64; 256]; KERNEL_MAX_CPUS] =
+    [const { [const { AtomicU64::new(0) }; 256] }; KERNEL_MAX_CPUS];
+
+/// Increments the counter of the vector on the current CPU.
+#[inline]
+pub fn count(vector: u8) {
+    if let Some(counters) = COUNTERS.get(cpu::current_id() as usize) {
+        counters[usize::from(vector)].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns how many times the vector was received by the CPU.
+pub fn get(cpu_id: u64, vector: u8) -> u64 {
+    COUNTERS.get(cpu_id as usize).map_or(0, |counters| {
+        counters[usize::from(vector)].load(Ordering::Relaxed)
+    })
+}
+
+/// Returns how many times the vector was received by all CPUs.
+pub fn total(vector: u8) -> u64 {
+    cpu::online().map(|cpu_id| get(cpu_id, vector)).sum()
+}
+
+/// Writes `/proc/interrupts` like table with every vector that has been received at least once.
+///
+/// # Errors
+///
+/// Will return an error if the writer fails.
+pub fn write_table(w: &mut impl Write) -> core::fmt::Result {
+    write!(w, "{:>5}", "")?;
+    for cpu_id in cpu::online() {
+        write!(w, " {:>8}{cpu_id:<2}", "CPU")?;
+    }
+    writeln!(w, "  {:<6} NAME", "GSI")?;
+
+    for vector in 0..=u8::MAX {
+        if total(vector) == 0 {
+            continue;
+        }
+
+        write!(w, "{vector:>4}:")?;
+        for cpu_id in cpu::online() {
+            write!(w, " {:>10}", get(cpu_id, vector))?;
+        }
+
+        match io_apic::IO_APICS.source_of(vector) {
+            Some(gsi) => write!(w, "  {gsi:<6}")?,
+            None => write!(w, "  {:<6}", "-")?,
+        }
+
+        writeln!(w, " {}", vector::name(vector))?;
+    }
+
+    Ok(())
+}
+
+/// Dumps the counters table to the serial port.
+pub fn dump() {
+    let mut writer = serial::COM1.lock();
+
+    let _ = write_table(&mut *writer);
+}
@@ -0,0 +1,110 @@
+//! Interrupt vectors layout.
+
+use core::ops::RangeInclusive;
+
+use spin::{Mutex, RwLock};
+
+/// First vector of the legacy ISA IRQs routed through the I/O APIC.
+pub const IRQ_BASE: u8 = 32;
+
+pub const PIT: u8 = IRQ_BASE;
+pub const KEYBOARD: u8 = IRQ_BASE + 1;
+pub const CASCADE: u8 = IRQ_BASE + 2;
+pub const COM2: u8 = IRQ_BASE + 3;
+pub const COM1: u8 = IRQ_BASE + 4;
+pub const LPT2: u8 = IRQ_BASE + 5;
+pub const FLOPPY: u8 = IRQ_BASE + 6;
+pub const LPT1: u8 = IRQ_BASE + 7;
+pub const RTC: u8 = IRQ_BASE + 8;
+pub const PCI1: u8 = IRQ_BASE + 9;
+pub const PCI2: u8 = IRQ_BASE + 10;
+pub const PCI3: u8 = IRQ_BASE + 11;
+pub const MOUSE: u8 = IRQ_BASE + 12;
+pub const FPU: u8 = IRQ_BASE + 13;
+pub const ATA1: u8 = IRQ_BASE + 14;
+pub const ATA2: u8 = IRQ_BASE + 15;
+
+pub const LAPIC_TIMER: u8 = 48;
+pub const LAPIC_ERROR: u8 = 49;
+pub const SPURIOUS: u8 = 50;
+
+/// Vectors available for dynamic allocation, e.g. for MSI.
+pub const DYNAMIC: RangeInclusive<u8> = 64..=239;
+
+static ALLOCATED: Mutex<[u64; 4]> = Mutex::new([0; 4]);
+static NAMES: RwLock<[Option<&'static str>; 256]> = RwLock::new([None; 256]);
+
+/// Allocates `count` contiguous vectors aligned to `count`, which must be a power of two.
+///
+/// Returns the first allocated vector.
+pub fn allocate(count: u8, name: &'static str) -> Option<u8> {
+    assert!(
+        count.is_power_of_two(),
+        "vectors count must be a power of two"
+    );
+
+    let mut allocated = ALLOCATED.lock();
+
+    let is_free = |vector: u8| allocated[usize::from(vector / 64)] & (1 << (vector % 64)) == 0;
+
+    let first = DYNAMIC.start().next_multiple_of(count);
+    let base = (first..=DYNAMIC.end() + 1 - count)
+        .step_by(usize::from(count))
+        .find(|base| (*base..*base + count).all(is_free))?;
+
+    let mut names = NAMES.write();
+
+    for vector in base..base + count {
+        allocated[usize::from(vector / 64)] |= 1 << (vector % 64);
+        names[usize::from(vector)] = Some(name);
+    }
+
+    Some(base)
+}
+
+/// Frees vectors previously returned by [`allocate`].
+pub fn free(base: u8, count: u8) {
+    let mut allocated = ALLOCATED.lock();
+    let mut names = NAMES.write();
+
+    for vector in base..base + count {
+        allocated[usize::from(vector / 64)] &= !(1 << (vector % 64));
+        names[usize::from(vector)] = None;
+    }
+}
+
+/// Returns a short human readable name of the vector.
+pub fn name(vector: u8) -> &'static str {
+    // Called by the panic handler, which may interrupt an allocation holding the lock.
+    if let Some(name) = NAMES
+        .try_read()
+        .and_then(|names| names[usize::from(vector)])
+    {
+        return name;
+    }
+
+    match vector {
+        0..=31 => "exception",
+        PIT => "pit",
+        KEYBOARD => "keyboard",
+        CASCADE => "cascade",
+        COM2 => "com2",
+        COM1 => "com1",
+        LPT2 => "lpt2",
+        FLOPPY => "floppy",
+        LPT1 => "lpt1",
+        RTC => "rtc",
+        PCI1 => "pci1",
+        PCI2 => "pci2",
+        PCI3 => "pci3",
+        MOUSE => "mouse",
+        FPU => "fpu",
+        ATA1 => "ata1",
+        ATA2 => "ata2",
+        LAPIC_TIMER => "lapic timer",
+        LAPIC_ERROR => "lapic error",
+        SPURIOUS => "spurious",
+        vector if DYNAMIC.contains(&vector) => "dynamic",
+        _ => "unknown",
+    }
+}
@@ -0,0 +1,200 @@
+//! Hard lockup detector.
+//!
+//! A CPU makes progress while it receives timer interrupts, so a CPU stuck with interrupts
+//! disabled stops counting them. With an architectural PMU every CPU checks its own progress from
+//! the cycle counter overflow NMI. Otherwise CPUs watch each other from the timer interrupt and send
+//! an NMI to the stuck buddy, so it dumps its state from the NMI handler.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use raw_cpuid::CpuId;
+use x86_64::registers::model_specific::Msr;
+
+use crate::devices::{cpu, lapic_timer, local_apic};
+use crate::interrupts::exception::ExceptionFrame;
+use crate::prelude::*;
+
+const IA32_PMC0: u32 = 0xC1;
+const IA32_PERFEVTSEL0: u32 = 0x186;
+const IA32_PERF_GLOBAL_STATUS: u32 = 0x38E;
+const IA32_PERF_GLOBAL_CTRL: u32 = 0x38F;
+const IA32_PERF_GLOBAL_OVF_CTRL: u32 = 0x390;
+
+/// Unhalted core cycles event, counted in both rings with interrupt on overflow.
+const EVTSEL_CORE_CYCLES: u64 = 0x3C | 1 << 16 | 1 << 17 | 1 << 20 | 1 << 22;
+
+/// Performance counter NMIs per second, more on CPUs too fast for the 31-bit period.
+const NMI_PER_SEC: u64 = 2;
+/// Assumed CPU frequency if CPUID doesn't report it.
+const DEFAULT_CPU_HZ: u64 = 2_000_000_000;
+
+/// Timer ticks of the watching CPU without buddy progress.
+const BUDDY_TIMEOUT_TICKS: u64 = KERNEL_WATCHDOG_TIMEOUT * KERNEL_TIMER_HZ;
+
+/// CPUs watching themselves with the performance counter.
+static PMU_CPUS: AtomicU64 = AtomicU64::new(0);
+/// CPUs which stopped making progress, set by the buddy before NMI.
+static SUSPECTED: [AtomicBool; KERNEL_MAX_CPUS] =
+    [const { AtomicBool::new(false) }; KERNEL_MAX_CPUS];
+
+#[thread_local]
+static mut PERIOD: u64 = 0;
+/// NMIs without progress making the timeout, the period is clamped on fast CPUs.
+#[thread_local]
+static mut STALE_LIMIT: u64 = 0;
+#[thread_local]
+static mut LAST_TICKS: u64 = 0;
+#[thread_local]
+static mut STALE_NMIS: u64 = 0;
+
+#[thread_local]
+static mut BUDDY: u64 = u64::MAX;
+#[thread_local]
+static mut BUDDY_LAST_TICKS: u64 = 0;
+#[thread_local]
+static mut BUDDY_STALE_TICKS: u64 = 0;
+
+/// Enables the watchdog on the current CPU, the performance counter is used when available.
+pub fn init() {
+    let cpu_id = cpu::current_id();
+
+    let pmu = CpuId::new()
+        .get_performance_monitoring_info()
+        .is_some_and(|info| {
+            info.version_id() >= 2
+                && info.number_of_counters() > 0
+                && !info.is_core_cyc_ev_unavailable()
+        });
+
+    if !pmu {
+        log::debug!("CPU{cpu_id} NMI watchdog enabled, buddy heartbeat");
+        return;
+    }
+
+    let hz = cpu::tsc_frequency().unwrap_or(DEFAULT_CPU_HZ);
+
+    unsafe {
+        // Counter writes are sign extended from 32 bits.
+        PERIOD = (hz / NMI_PER_SEC).min(i32::MAX as u64);
+        STALE_LIMIT = KERNEL_WATCHDOG_TIMEOUT.saturating_mul(hz).div_ceil(PERIOD);
+
+        arm_counter();
+        write_msr(IA32_PERFEVTSEL0, EVTSEL_CORE_CYCLES);
+        write_msr(IA32_PERF_GLOBAL_CTRL, read_msr(IA32_PERF_GLOBAL_CTRL) | 1);
+    }
+
+    local_apic::LOCAL_APIC.set_perf_counter_nmi();
+
+    if cpu_id < u64::BITS.into() {
+        PMU_CPUS.fetch_or(1 << cpu_id, Ordering::SeqCst);
+    }
+
+    log::debug!("CPU{cpu_id} NMI watchdog enabled, performance counter");
+}
+
+/// Handles the watchdog NMI, returns `false` if the NMI is not sent by the watchdog.
+///
+/// # Panics
+///
+/// Will panic if the current CPU is locked up.
+pub fn handle_nmi(frame: &mut ExceptionFrame) -> bool {
+    let cpu_id = cpu::current_id();
+
+    let suspected = SUSPECTED
+        .get(cpu_id as usize)
+        .is_some_and(|suspected| suspected.swap(false, Ordering::SeqCst));
+
+    if suspected {
+        lockup(cpu_id, frame);
+    }
+
+    if !is_pmu_cpu(cpu_id) || unsafe { read_msr(IA32_PERF_GLOBAL_STATUS) } & 1 == 0 {
+        return false;
+    }
+
+    unsafe {
+        write_msr(IA32_PERF_GLOBAL_OVF_CTRL, 1);
+        arm_counter();
+    }
+
+    local_apic::LOCAL_APIC.set_perf_counter_nmi();
+
+    let ticks = lapic_timer::ticks(cpu_id);
+
+    unsafe {
+        if ticks != LAST_TICKS {
+            LAST_TICKS = ticks;
+            STALE_NMIS = 0;
+            return true;
+        }
+
+        STALE_NMIS += 1;
+
+        if STALE_NMIS >= STALE_LIMIT {
+            lockup(cpu_id, frame);
+        }
+    }
+
+    true
+}
+
+/// Watches the progress of the buddy CPU, must be called from the timer interrupt.
+pub fn tick() {
+    let cpu_id = cpu::current_id();
+
+    // The next online CPU, the last one watches the first.
+    let Some(buddy) = cpu::online()
+        .find(|id| *id > cpu_id)
+        .or_else(|| cpu::online().next())
+        .filter(|id| *id != cpu_id && !is_pmu_cpu(*id))
+    else {
+        return;
+    };
+
+    let ticks = lapic_timer::ticks(buddy);
+
+    unsafe {
+        if buddy != BUDDY || ticks != BUDDY_LAST_TICKS {
+            BUDDY = buddy;
+            BUDDY_LAST_TICKS = ticks;
+            BUDDY_STALE_TICKS = 0;
+            return;
+        }
+
+        BUDDY_STALE_TICKS += 1;
+
+        if BUDDY_STALE_TICKS == BUDDY_TIMEOUT_TICKS {
+            SUSPECTED[buddy as usize].store(true, Ordering::SeqCst);
+
+            if let Some(apic_id) = cpu::apic_id(buddy) {
+                local_apic::LOCAL_APIC.send_nmi(apic_id);
+            }
+        }
+    }
+}
+
+/// Reports the lockup with the state of the interrupted code.
+fn lockup(cpu_id: u64, frame: &mut ExceptionFrame) -> ! {
+    // Don't log here, the CPU may be stuck holding the console lock, the panic handler
+    // bypasses it.
+    crate::panic::set_exception_frame(frame);
+
+    panic!("CPU{cpu_id} hard lockup, no timer interrupts\n{frame}")
+}
+
+fn is_pmu_cpu(cpu_id: u64) -> bool {
+    cpu_id < u64::BITS.into() && PMU_CPUS.load(Ordering::SeqCst) & (1 << cpu_id) != 0
+}
+
+/// Sets the counter to overflow after the period.
+unsafe fn arm_counter() {
+    write_msr(IA32_PMC0, PERIOD.wrapping_neg());
+}
+
+unsafe fn read_msr(msr: u32) -> u64 {
+    Msr::new(msr).read()
+}
+
+unsafe fn write_msr(msr: u32, value: u64) {
+    Msr::new(msr).write(value);
+}
@@ -7,15 +7,22 @@ extern crate alloc;
 use bootloader_api::info::TlsTemplate;
 use spin::Once;
 
+mod backtrace;
 mod debug;
 mod devices;
 mod gdt;
 mod idt;
+mod input;
 mod interrupts;
 mod logger;
 mod memory;
 mod paging;
+pub mod panic;
 mod prelude;
+mod ring_buffer;
+mod symbols;
+mod time;
+mod timer;
 
 static PHYS_OFFSET: Once<u64> = Once::new();
 static TLS_TEMPLATE: Once<TlsTemplate> = Once::new();
@@ -45,6 +52,7 @@ pub fn entry(info: &'static mut bootloader_api::BootInfo) -> ! {
     // Init memory and TLS.
     memory::init(phys_offset, &info.memory_regions);
     paging::init(0, tls_template);
+    devices::cpu::init_current(0);
 
     // Init GDT and IDT with TLS.
     gdt::init();
@@ -56,12 +64,20 @@ pub fn entry(info: &'static mut bootloader_api::BootInfo) -> ! {
     // Init devices.
     devices::init(phys_offset, info.rsdp_addr.into_option());
 
+    interrupts::watchdog::init();
     interrupts::enable();
 
+    // Serial interrupts are delivered to the BSP, so buffered writes are drained from now on.
+    devices::serial::enable_buffering();
+
     log::info!("Spiky OS started...");
 
+    // The console is the only consumer of input for now.
+    let mut input = input::reader();
+
     loop {
-        interrupts::hlt();
+        let event = input.read();
+        devices::display::handle_input(&event.event);
     }
 }
 
@@ -84,6 +100,7 @@ fn ap_entry(cpu_id: u64) -> ! {
 
     // Init TLS.
     paging::init(cpu_id, tls_template);
+    devices::cpu::init_current(cpu_id);
 
     // Init GDT and IDT with TLS.
     gdt::init();
@@ -92,6 +109,10 @@ fn ap_entry(cpu_id: u64) -> ! {
     // Init devices.
     devices::init_ap();
 
+    // Timer interrupts are required by the watchdog to see progress of the CPU.
+    interrupts::watchdog::init();
+    interrupts::enable();
+
     log::info!("AP CORE_{cpu_id} started...");
 
     loop {
@@ -14,9 +14,5 @@ static BOOTLOADER_CONFIG: BootloaderConfig = {
 
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
-    log::error!("{info}");
-
-    loop {
-        core::hint::spin_loop();
-    }
+    kernel::panic::handle(info)
 }
@@ -1,7 +1,7 @@
 use bootloader_api::info::MemoryRegions;
 use spin::Mutex;
 use x86_64::registers::control::Cr3;
-use x86_64::structures::paging::PageTable;
+use x86_64::structures::paging::{OffsetPageTable, PageTable, Translate};
 use x86_64::VirtAddr;
 
 use frame_allocator::FrameAllocator;
@@ -30,6 +30,19 @@ pub fn init_heap() {
     heap::init();
 }
 
+/// Checks that the address is mapped without taking the [`KERNEL_PAGE_MAPPER`] lock, so it can
+/// be used from panic and exception handlers.
+pub fn is_mapped(addr: VirtAddr) -> bool {
+    let Some(phys_offset) = crate::PHYS_OFFSET.get().copied() else {
+        return false;
+    };
+
+    let phys_offset = VirtAddr::new(phys_offset);
+    let table = unsafe { OffsetPageTable::new(active_level_4_table(phys_offset), phys_offset) };
+
+    table.translate_addr(addr).is_some()
+}
+
 unsafe fn active_level_4_table(phys_offset: VirtAddr) -> &'static mut PageTable {
     let (level_4_table_frame, _) = Cr3::read();
 
@@ -1,5 +1,5 @@
 use bootloader_api::info::TlsTemplate;
-use x86_64::registers::model_specific::FsBase;
+use x86_64::registers::model_specific::{FsBase, KernelGsBase};
 use x86_64::structures::paging::{Page, PageTableFlags};
 use x86_64::VirtAddr;
 
@@ -57,6 +57,8 @@ pub fn init(cpu_id: u64, mut tls: TlsTemplate) {
     }
 
     FsBase::write(end);
+    // Restored by exceptions raised in user mode, FS base is the user's one there.
+    KernelGsBase::write(end);
 
     test();
 }
@@ -0,0 +1,273 @@
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+
+use crate::backtrace::Backtrace;
+use crate::devices::{cpu, display, local_apic, rtc, serial};
+use crate::interrupts::{self, exception::ExceptionFrame};
+use crate::prelude::*;
+use crate::symbols;
+use crate::time;
+
+const NO_CPU: u64 = u64::MAX;
+
+/// Bounds the wait for other CPUs to stop, some of them may not respond to NMI.
+const STOP_WAIT_SPINS: usize = 100_000_000;
+
+/// CPU that panicked first, only it prints the report.
+static PANIC_CPU: AtomicU64 = AtomicU64::new(NO_CPU);
+/// Number of CPUs stopped by the panicking CPU.
+static STOPPED_CPUS: AtomicUsize = AtomicUsize::new(0);
+
+static REBOOT_DELAY: AtomicU64 = AtomicU64::new(KERNEL_PANIC_REBOOT_DELAY);
+
+/// Fatal exception frames of CPUs, reported instead of the panic site registers.
+static EXCEPTION_FRAMES: [AtomicPtr<ExceptionFrame>; KERNEL_MAX_CPUS] =
+    [const { AtomicPtr::new(ptr::null_mut()) }; KERNEL_MAX_CPUS];
+
+/// Sets seconds before reboot after panic, zero disables reboot.
+pub fn set_reboot_delay(secs: u64) {
+    REBOOT_DELAY.store(secs, Ordering::Relaxed);
+}
+
+/// Returns `true` if any CPU has panicked.
+pub fn is_panicking() -> bool {
+    PANIC_CPU.load(Ordering::SeqCst) != NO_CPU
+}
+
+/// Saves the frame of a fatal exception on the current CPU, must be called right before panic.
+pub(crate) fn set_exception_frame(frame: &mut ExceptionFrame) {
+    if let Some(slot) = EXCEPTION_FRAMES.get(current_id() as usize) {
+        slot.store(frame, Ordering::SeqCst);
+    }
+}
+
+/// Halts the current CPU on request of the panicking CPU, called from the NMI handler.
+pub(crate) fn stop_current() -> ! {
+    STOPPED_CPUS.fetch_add(1, Ordering::SeqCst);
+
+    halt()
+}
+
+/// Stops all CPUs and prints the crash report to COM1, then reboots if configured.
+pub fn handle(info: &PanicInfo) -> ! {
+    interrupts::disable();
+
+    let cpu_id = current_id();
+
+    // Only the first panic is reported, nested and concurrent panics just stop the CPU.
+    if PANIC_CPU
+        .compare_exchange(NO_CPU, cpu_id, Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        stop_current();
+    }
+
+    stop_other_cpus();
+
+    // Other CPUs are stopped, so the locks can't be released by their owners anymore.
+    unsafe {
+        serial::COM1.force_unlock();
+        serial::COM1.lock().force_sync();
+        display::DISPLAY.force_unlock();
+    }
+
+    let frame = EXCEPTION_FRAMES
+        .get(cpu_id as usize)
+        .map(|slot| slot.load(Ordering::SeqCst))
+        .and_then(|frame| unsafe { frame.as_ref() });
+
+    let backtrace = match frame {
+        Some(frame) => Backtrace::from_frame(frame.rip, frame.regs.rbp),
+        None => Backtrace::current(),
+    };
+
+    let report = CrashReport {
+        cpu_id,
+        info,
+        frame,
+        backtrace,
+    };
+
+    let _ = write!(serial::COM1.lock(), "{report}");
+    // Interrupt storms and devices gone silent show up in the counters.
+    interrupts::stats::dump();
+    // The interrupted writer may have left an unfinished escape sequence and colors, CAN cancels it.
+    let _ = writeln!(
+        display::DISPLAY.lock(),
+        "\x18\x1b[0mKERNEL PANIC\n{info}\n{backtrace}"
+    );
+
+    let delay = REBOOT_DELAY.load(Ordering::Relaxed);
+
+    if delay != 0 {
+        let _ = writeln!(serial::COM1.lock(), "Rebooting in {delay} seconds...");
+
+        unsafe { rtc::RTC.force_unlock() };
+
+        let mut rtc = rtc::RTC.lock();
+        let start = rtc.time().unix_time();
+
+        while rtc.time().unix_time() < start + delay {
+            core::hint::spin_loop();
+        }
+
+        cpu::reset();
+    }
+
+    halt()
+}
+
+fn current_id() -> u64 {
+    cpu::try_current_id().unwrap_or(0)
+}
+
+fn stop_other_cpus() {
+    let others = cpu::online().count().saturating_sub(1);
+
+    local_apic::LOCAL_APIC.send_nmi_others();
+
+    for _ in 0..STOP_WAIT_SPINS {
+        if STOPPED_CPUS.load(Ordering::SeqCst) >= others {
+            break;
+        }
+
+        core::hint::spin_loop();
+    }
+}
+
+fn halt() -> ! {
+    loop {
+        interrupts::disable();
+        interrupts::hlt();
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////////////////////////
+// Crash report
+////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Machine readable crash record: one `key=value` pair per line between the markers.
+struct CrashReport<'a> {
+    cpu_id: u64,
+    info: &'a PanicInfo<'a>,
+    frame: Option<&'a ExceptionFrame>,
+    backtrace: Backtrace,
+}
+
+impl fmt::Display for CrashReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "\n---[ CRASH REPORT BEGIN ]---")?;
+        writeln!(f, "cpu={}", self.cpu_id)?;
+
+        match self.info.location() {
+            Some(location) => writeln!(f, "location={location}")?,
+            None => writeln!(f, "location=unknown")?,
+        }
+
+        write!(f, "message=")?;
+        write!(Escaped(f), "{}", self.info.message())?;
+        writeln!(f)?;
+
+        let tsc = unsafe { core::arch::x86_64::_rdtsc() };
+        writeln!(f, "uptime_tsc={tsc}")?;
+        // The clock source may keep per-CPU state in TLS, which is not set up early on APs.
+        let uptime = match cpu::try_current_id() {
+            Some(_) => time::monotonic_now(),
+            None => time::monotonic_now_global(),
+        };
+        writeln!(f, "uptime_ms={}", uptime / 1_000_000)?;
+
+        match self.frame {
+            Some(frame) => write_exception_frame(f, frame)?,
+            None => write_current_registers(f)?,
+        }
+
+        writeln!(f, "reg.cr0={:#x}", Cr0::read_raw())?;
+        writeln!(f, "reg.cr2={:#x}", Cr2::read_raw())?;
+        writeln!(f, "reg.cr3={:#x}", Cr3::read().0.start_address().as_u64())?;
+        writeln!(f, "reg.cr4={:#x}", Cr4::read_raw())?;
+
+        let mut frames = self.backtrace.frames();
+
+        for (idx, addr) in frames.by_ref().enumerate() {
+            writeln!(f, "backtrace.{idx}={}", symbols::Address(addr))?;
+        }
+
+        writeln!(f, "backtrace_truncated={}", frames.is_truncated())?;
+        writeln!(f, "---[ CRASH REPORT END ]---")
+    }
+}
+
+fn write_exception_frame(f: &mut fmt::Formatter<'_>, frame: &ExceptionFrame) -> fmt::Result {
+    let r = &frame.regs;
+
+    writeln!(f, "exception.vector={}", frame.vector)?;
+    writeln!(f, "exception.error_code={:#x}", frame.error_code)?;
+
+    for (name, value) in [
+        ("rip", frame.rip),
+        ("rsp", frame.rsp),
+        ("rflags", frame.rflags),
+        ("cs", frame.cs),
+        ("ss", frame.ss),
+        ("rax", r.rax),
+        ("rbx", r.rbx),
+        ("rcx", r.rcx),
+        ("rdx", r.rdx),
+        ("rsi", r.rsi),
+        ("rdi", r.rdi),
+        ("rbp", r.rbp),
+        ("r8", r.r8),
+        ("r9", r.r9),
+        ("r10", r.r10),
+        ("r11", r.r11),
+        ("r12", r.r12),
+        ("r13", r.r13),
+        ("r14", r.r14),
+        ("r15", r.r15),
+    ] {
+        writeln!(f, "reg.{name}={value:#x}")?;
+    }
+
+    Ok(())
+}
+
+/// Registers of the panic site, general purpose registers are meaningless here.
+fn write_current_registers(f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let (rsp, rbp): (u64, u64);
+
+    unsafe {
+        core::arch::asm!(
+            "mov {}, rsp",
+            "mov {}, rbp",
+            out(reg) rsp,
+            out(reg) rbp,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+
+    writeln!(f, "reg.rsp={rsp:#x}")?;
+    writeln!(f, "reg.rbp={rbp:#x}")?;
+    writeln!(f, "reg.rflags={:#x}", x86_64::registers::rflags::read_raw())
+}
+
+/// Escapes line breaks to keep a value on a single line.
+struct Escaped<'a, 'b>(&'a mut fmt::Formatter<'b>);
+
+impl Write for Escaped<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (idx, line) in s.split('\n').enumerate() {
+            if idx != 0 {
+                self.0.write_str("\\n")?;
+            }
+
+            self.0.write_str(line)?;
+        }
+
+        Ok(())
+    }
+}
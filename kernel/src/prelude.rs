@@ -5,6 +5,10 @@ pub const PAGE_OFFSET_MASK: u64 = PAGE_SIZE - 1;
 pub const KERNEL_BACKUP_STACK_SIZE: u64 = 65536; // 64 KB
 pub const KERNEL_BACKUP_STACK_INDEX: u16 = 0;
 
+pub const KERNEL_MAX_CPUS: usize = 64;
+pub const KERNEL_PANIC_REBOOT_DELAY: u64 = 0; // seconds, zero disables reboot
+pub const KERNEL_TIMER_HZ: u64 = 100;
+pub const KERNEL_WATCHDOG_TIMEOUT: u64 = 10; // seconds
 pub const KERNEL_PERCPU_SIZE: u64 = 0x20000;
 pub const KERNEL_PERCPU_OFFSET: u64 = 0xffff_fd80_0000_0000;
 
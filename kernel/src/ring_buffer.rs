@@ -0,0 +1,113 @@
+//! Lock-free single producer, single consumer ring buffer, e.g. for passing data from an interrupt
+//! handler.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct RingBuffer<T: Copy, const N: usize> {
+    slots: [UnsafeCell<MaybeUninit<T>>; N],
+    // Positions grow forever, the slot is the position modulo `N`.
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+// Safety: a slot is accessed either by the producer before the tail passes it, or by the consumer
+// after it, never by both.
+unsafe impl<T: Copy + Send, const N: usize> Sync for RingBuffer<T, N> {}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            slots: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Appends the value, returns it back if the buffer is full.
+    ///
+    /// Must not be called concurrently with another `push`.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+
+        if tail.wrapping_sub(self.head.load(Ordering::Acquire)) == N {
+            return Err(value);
+        }
+
+        unsafe { (*self.slots[tail % N].get()).write(value) };
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Removes the oldest value.
+    ///
+    /// Must not be called concurrently with another `pop`.
+    pub fn pop(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let value = unsafe { (*self.slots[head % N].get()).assume_init() };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tail
+            .load(Ordering::Acquire)
+            .wrapping_sub(self.head.load(Ordering::Acquire))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[allow(dead_code, reason = "only the tests check for a full buffer")]
+    pub fn is_full(&self) -> bool {
+        self.len() == N
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RingBuffer;
+
+    #[test]
+    fn push_pop() {
+        let ring = RingBuffer::<u8, 4>::new();
+        assert!(ring.is_empty());
+        assert_eq!(ring.pop(), None);
+
+        for value in 0..4 {
+            assert_eq!(ring.push(value), Ok(()));
+        }
+
+        assert!(ring.is_full());
+        assert_eq!(ring.push(4), Err(4));
+
+        assert_eq!(ring.pop(), Some(0));
+        assert_eq!(ring.push(4), Ok(()));
+
+        for value in 1..5 {
+            assert_eq!(ring.pop(), Some(value));
+        }
+
+        assert_eq!(ring.pop(), None);
+    }
+
+    #[test]
+    fn wrap_around() {
+        let ring = RingBuffer::<u32, 3>::new();
+
+        for value in 0..100 {
+            assert_eq!(ring.push(value), Ok(()));
+            assert_eq!(ring.len(), 1);
+            assert_eq!(ring.pop(), Some(value));
+        }
+    }
+}
@@ -0,0 +1,98 @@
+//! Kernel symbol table, filled by the build script after the kernel is linked.
+
+use core::ptr::addr_of;
+
+const SYMBOLS_SIZE: usize = 1024 * 1024; // 1 MB
+const ENTRY_SIZE: usize = 24;
+
+#[repr(C, align(8))]
+struct SymbolTable {
+    magic: [u8; 8],
+    link_addr: u64,
+    count: u64,
+    data: [u8; SYMBOLS_SIZE],
+}
+
+// Mutable, so the compiler doesn't assume the table is always empty.
+#[used]
+#[link_section = ".ksyms"]
+static mut SYMBOLS: SymbolTable = SymbolTable {
+    magic: *b"KSYMTAB\0",
+    link_addr: 0,
+    count: 0,
+    data: [0; SYMBOLS_SIZE],
+};
+
+/// Function symbol containing an address.
+#[derive(Debug, Copy, Clone)]
+pub struct Symbol {
+    pub name: &'static str,
+    pub offset: u64,
+}
+
+impl core::fmt::Display for Symbol {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}+{:#x}", self.name, self.offset)
+    }
+}
+
+/// Formats the address with the symbol containing it, e.g. `0xffff800000001234 <main+0x34>`.
+#[derive(Debug, Copy, Clone)]
+pub struct Address(pub u64);
+
+impl core::fmt::Display for Address {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match lookup(self.0) {
+            Some(symbol) => write!(f, "{:#018x} <{symbol}>", self.0),
+            None => write!(f, "{:#018x} <unknown>", self.0),
+        }
+    }
+}
+
+/// Returns the function symbol containing the address.
+pub fn lookup(addr: u64) -> Option<Symbol> {
+    let table = unsafe { &*addr_of!(SYMBOLS) };
+
+    if table.count == 0 {
+        return None;
+    }
+
+    // The kernel is position independent, so translate the address to the link time address.
+    let load_offset = (addr_of!(SYMBOLS) as u64).wrapping_sub(table.link_addr);
+    let addr = addr.wrapping_sub(load_offset);
+
+    let count = usize::try_from(table.count).ok()?;
+    let (entries, _) = table
+        .data
+        .get(..count * ENTRY_SIZE)?
+        .as_chunks::<ENTRY_SIZE>();
+    let names = &table.data[count * ENTRY_SIZE..];
+
+    // Index of the first symbol after the address.
+    let idx = entries.partition_point(|entry| read_u64(entry, 0) <= addr);
+    let entry = &entries[idx.checked_sub(1)?];
+
+    let start = read_u64(entry, 0);
+    let size = read_u64(entry, 8);
+
+    if size != 0 && addr >= start + size {
+        return None;
+    }
+
+    let name_start = usize::try_from(read_u32(entry, 16)).ok()?;
+    let name_end = name_start + usize::try_from(read_u32(entry, 20)).ok()?;
+    let name = core::str::from_utf8(names.get(name_start..name_end)?).ok()?;
+
+    Some(Symbol {
+        name,
+        offset: addr - start,
+    })
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
@@ -0,0 +1,355 @@
+//! Clock sources, calibration and timekeeping.
+//!
+//! The monotonic clock counts nanoseconds since boot with the best available clock source, the
+//! wall clock adds the RTC time read at boot to it.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Once;
+
+use crate::devices::{hpet, pit, pm_timer, rtc, tsc};
+
+pub const NSEC_PER_SEC: u64 = 1_000_000_000;
+pub const USEC_PER_SEC: u64 = 1_000_000;
+pub const SECS_PER_DAY: u64 = 86_400;
+
+/// Clock source of the monotonic clock.
+static CLOCK: Once<&'static dyn ClockSource> = Once::new();
+/// Counter of the clock source at boot.
+static BOOT_COUNT: AtomicU64 = AtomicU64::new(0);
+/// Unix time in nanoseconds at boot.
+static BOOT_REALTIME: AtomicU64 = AtomicU64::new(0);
+
+/// Free running counter with a fixed frequency.
+pub trait ClockSource: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Returns the counter frequency in Hz.
+    fn frequency(&self) -> u64;
+
+    /// Returns the mask of valid counter bits, the counter wraps around it.
+    fn mask(&self) -> u64;
+
+    fn read(&self) -> u64;
+
+    /// Reads the counter without per-CPU state, which is not set up early on APs.
+    fn read_global(&self) -> u64 {
+        self.read()
+    }
+
+    /// Returns counter ticks elapsed since `start`, handles a single wrap around.
+    fn elapsed(&self, start: u64) -> u64 {
+        self.read().wrapping_sub(start) & self.mask()
+    }
+}
+
+/// Busy waits on the clock source for at least `us` microseconds, the wait must be shorter than
+/// the wrap around period of the counter.
+pub fn busy_wait(source: &dyn ClockSource, us: u64) {
+    let ticks = u128::from(source.frequency()) * u128::from(us) / u128::from(USEC_PER_SEC);
+    let start = source.read();
+
+    while u128::from(source.elapsed(start)) < ticks {
+        core::hint::spin_loop();
+    }
+}
+
+/// Busy waits for at least `us` microseconds.
+pub fn udelay(us: u64) {
+    if let Some(clock) = CLOCK.get() {
+        busy_wait(*clock, us);
+        return;
+    }
+
+    // Early boot, the reference clocks may wrap around or be limited, so wait in chunks.
+    let mut left = us;
+
+    while left > 0 {
+        let chunk = left.min(pit::MAX_WAIT_US);
+
+        if calibration_wait(chunk).is_none() {
+            log::warn!("No clock to wait on, delay is skipped");
+            return;
+        }
+
+        left -= chunk;
+    }
+}
+
+/// Busy waits for at least `ms` milliseconds.
+pub fn mdelay(ms: u64) {
+    udelay(ms.saturating_mul(1000));
+}
+
+/// Busy waits on the reference clock used for calibration of other timers: HPET, ACPI PM timer or
+/// PIT, the first one present.
+///
+/// Returns the name of the reference clock, `None` if there is no one.
+pub fn calibration_wait(us: u64) -> Option<&'static str> {
+    if hpet::HPET.is_present() {
+        busy_wait(&hpet::HPET, us);
+        return Some(hpet::HPET.name());
+    }
+
+    if pm_timer::PM_TIMER.is_present() {
+        busy_wait(&pm_timer::PM_TIMER, us);
+        return Some(pm_timer::PM_TIMER.name());
+    }
+
+    pit::PIT.lock().wait_us(us).then_some("PIT")
+}
+
+/// Selects the clock source and reads the wall clock, must be called once on the BSP after clock
+/// sources initialized.
+pub fn init() {
+    let tsc_usable = tsc::TSC.frequency() != 0;
+
+    // Wrap around of a 32-bit HPET counter takes minutes, the monotonic clock can't handle it.
+    let clock: &'static dyn ClockSource = if tsc_usable && tsc::TSC.is_invariant() {
+        &tsc::TSC
+    } else if hpet::HPET.is_present() && hpet::HPET.mask() == u64::MAX {
+        &hpet::HPET
+    } else if tsc_usable {
+        &tsc::TSC
+    } else {
+        log::warn!("No clock source, monotonic clock is stopped");
+        return;
+    };
+
+    BOOT_COUNT.store(clock.read(), Ordering::SeqCst);
+    CLOCK.call_once(|| clock);
+
+    // Whole seconds, the RTC doesn't report fractions.
+    let boot_time = rtc::RTC.lock().time();
+    let boot_ns = boot_time
+        .unix_time()
+        .checked_mul(NSEC_PER_SEC)
+        .unwrap_or_else(|| {
+            log::warn!("RTC time {boot_time} is out of range, wall clock starts at the epoch");
+            0
+        });
+    let realtime = boot_ns.wrapping_sub(monotonic_now());
+    BOOT_REALTIME.store(realtime, Ordering::SeqCst);
+
+    log::debug!("Monotonic clock: {}, boot time: {boot_time}", clock.name());
+}
+
+/// Returns nanoseconds since boot, zero before the clock source is selected.
+pub fn monotonic_now() -> u64 {
+    let Some(clock) = CLOCK.get() else {
+        return 0;
+    };
+
+    let ticks = clock.elapsed(BOOT_COUNT.load(Ordering::Relaxed));
+
+    ticks_to_ns(*clock, ticks)
+}
+
+/// Returns nanoseconds since boot like [`monotonic_now`] without per-CPU state, so it works on CPUs
+/// without TLS, but may be off by the skew between CPUs.
+pub fn monotonic_now_global() -> u64 {
+    let Some(clock) = CLOCK.get() else {
+        return 0;
+    };
+
+    let ticks = clock
+        .read_global()
+        .wrapping_sub(BOOT_COUNT.load(Ordering::Relaxed))
+        & clock.mask();
+
+    ticks_to_ns(*clock, ticks)
+}
+
+fn ticks_to_ns(clock: &dyn ClockSource, ticks: u64) -> u64 {
+    let ns = u128::from(ticks) * u128::from(NSEC_PER_SEC) / u128::from(clock.frequency());
+
+    #[allow(clippy::cast_possible_truncation)]
+    {
+        ns as u64
+    }
+}
+
+/// Returns nanoseconds since the Unix epoch.
+#[allow(dead_code, reason = "nothing reads the wall clock yet")]
+pub fn realtime_now() -> u64 {
+    BOOT_REALTIME
+        .load(Ordering::Relaxed)
+        .wrapping_add(monotonic_now())
+}
+
+/// Calendar date and time in UTC, proleptic Gregorian calendar.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12.
+    pub month: u8,
+    /// 1 to 31.
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Converts seconds since the Unix epoch.
+    #[allow(dead_code, reason = "nothing reads the wall clock yet")]
+    pub fn from_unix_time(secs: u64) -> Self {
+        let days = secs / SECS_PER_DAY;
+        let secs = secs % SECS_PER_DAY;
+
+        // Days since 0000-03-01, so the leap day is the last day of a year.
+        let days = days + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let month = if month < 10 { month + 3 } else { month - 9 };
+        let year = era * 400 + year_of_era + u64::from(month <= 2);
+
+        #[allow(clippy::cast_possible_truncation)]
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (secs / 3600) as u8,
+            minute: (secs / 60 % 60) as u8,
+            second: (secs % 60) as u8,
+        }
+    }
+
+    /// Returns seconds since the Unix epoch, zero for dates before it.
+    pub fn unix_time(&self) -> u64 {
+        let secs = u64::from(self.hour) * 3600 + u64::from(self.minute) * 60;
+        let secs = secs + u64::from(self.second);
+
+        self.days_since_epoch()
+            .map_or(0, |days| days * SECS_PER_DAY + secs)
+    }
+
+    /// Returns `true` if all fields are in range, including the day of the month.
+    pub fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month)
+            && (1..=days_in_month(self.year, self.month)).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    fn days_since_epoch(&self) -> Option<u64> {
+        // Years start in March, so the leap day is the last day of a year.
+        let year = u64::from(self.year).checked_sub(u64::from(self.month <= 2))?;
+        let month = u64::from(self.month);
+        let month = if month > 2 { month - 3 } else { month + 9 };
+
+        let era = year / 400;
+        let year_of_era = year % 400;
+        let day_of_year = (153 * month + 2) / 5 + u64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+        (era * 146_097 + day_of_era).checked_sub(719_468)
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+pub fn is_leap_year(year: u16) -> bool {
+    year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+/// Returns the number of days in the month, zero for invalid months.
+pub fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+        DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    #[test]
+    fn unix_time_conversion() {
+        let cases = [
+            (0, date(1970, 1, 1, 0, 0, 0)),
+            (951_782_400, date(2000, 2, 29, 0, 0, 0)),
+            (951_868_799, date(2000, 2, 29, 23, 59, 59)),
+            (951_868_800, date(2000, 3, 1, 0, 0, 0)),
+            (1_234_567_890, date(2009, 2, 13, 23, 31, 30)),
+            (2_147_483_647, date(2038, 1, 19, 3, 14, 7)),
+            (4_107_542_399, date(2100, 2, 28, 23, 59, 59)),
+            (4_107_542_400, date(2100, 3, 1, 0, 0, 0)),
+            (4_133_980_799, date(2100, 12, 31, 23, 59, 59)),
+        ];
+
+        for (secs, datetime) in cases {
+            assert_eq!(DateTime::from_unix_time(secs), datetime, "{secs}");
+            assert_eq!(datetime.unix_time(), secs, "{datetime}");
+        }
+    }
+
+    #[test]
+    fn unix_time_round_trip_on_month_boundaries() {
+        for year in [1970, 1999, 2000, 2024, 2100, 2400] {
+            for month in 1..=12 {
+                for day in [1, days_in_month(year, month)] {
+                    let datetime = date(year, month, day, 12, 30, 45);
+                    assert_eq!(DateTime::from_unix_time(datetime.unix_time()), datetime);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn leap_years() {
+        assert!(is_leap_year(2000));
+        assert!(is_leap_year(2024));
+        assert!(!is_leap_year(1900));
+        assert!(!is_leap_year(2100));
+        assert!(!is_leap_year(2023));
+
+        assert_eq!(days_in_month(2000, 2), 29);
+        assert_eq!(days_in_month(2100, 2), 28);
+        assert_eq!(days_in_month(2023, 13), 0);
+    }
+
+    #[test]
+    fn validity() {
+        assert!(date(2024, 2, 29, 23, 59, 59).is_valid());
+        assert!(!date(2023, 2, 29, 0, 0, 0).is_valid());
+        assert!(!date(2023, 4, 31, 0, 0, 0).is_valid());
+        assert!(!date(2023, 0, 1, 0, 0, 0).is_valid());
+        assert!(!date(2023, 1, 1, 24, 0, 0).is_valid());
+    }
+
+    #[test]
+    fn dates_before_epoch() {
+        assert_eq!(date(1969, 12, 31, 23, 59, 59).unix_time(), 0);
+    }
+}
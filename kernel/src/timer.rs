@@ -0,0 +1,333 @@
+//! Kernel timers: call a function after a delay.
+//!
+//! Every CPU has a hierarchical timer wheel driven by its periodic timer ticks. Level `n` has
+//! [`SLOTS`] slots of `SLOTS^n` ticks, timers are cascaded to lower levels as their expiry gets
+//! closer. Expired timers run from the timer interrupt after end-of-interrupt with interrupts
+//! enabled, so callbacks can take locks used by interrupt handlers with interrupts disabled.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::mem;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::devices::{cpu, lapic_timer};
+use crate::interrupts;
+use crate::prelude::*;
+
+const LEVELS: usize = 4;
+const LEVEL_BITS: u64 = 6;
+const SLOTS: usize = 1 << LEVEL_BITS;
+const SLOT_MASK: u64 = (1 << LEVEL_BITS) - 1;
+
+/// Longest delay in ticks, about 46 hours at 100 Hz, longer delays are clamped.
+const MAX_DELAY: u64 = (1 << (LEVEL_BITS * LEVELS as u64)) - 1;
+
+pub type Callback = Box<dyn FnOnce() + Send>;
+
+static WHEELS: [Mutex<Wheel>; KERNEL_MAX_CPUS] =
+    [const { Mutex::new(Wheel::new(0)) }; KERNEL_MAX_CPUS];
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Set while expired timers of the current CPU run, a nested timer interrupt leaves them to it.
+#[thread_local]
+static mut RUNNING: bool = false;
+
+/// Handle of a pending timer.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TimerId {
+    cpu_id: u64,
+    id: u64,
+}
+
+/// Calls the callback on the current CPU after at least `delay_ms` milliseconds.
+///
+/// # Panics
+///
+/// Will panic if the current CPU id exceeds [`KERNEL_MAX_CPUS`].
+#[allow(dead_code, reason = "no driver uses kernel timers yet")]
+pub fn add_timer(delay_ms: u64, callback: impl FnOnce() + Send + 'static) -> TimerId {
+    let cpu_id = cpu::current_id();
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+    let callback = Box::new(callback);
+
+    without_interrupts(|| {
+        let mut wheel = WHEELS[cpu_id as usize].lock();
+        let expires = wheel.now + ms_to_ticks(delay_ms);
+
+        wheel.insert(Timer {
+            id,
+            expires,
+            callback,
+        });
+    });
+
+    TimerId { cpu_id, id }
+}
+
+/// Cancels the timer, returns `false` if it has already run or been cancelled.
+#[allow(dead_code, reason = "no driver uses kernel timers yet")]
+pub fn cancel_timer(timer: TimerId) -> bool {
+    without_interrupts(|| {
+        WHEELS[timer.cpu_id as usize]
+            .lock()
+            .remove(timer.id)
+            .is_some()
+    })
+}
+
+/// Moves the expiry of the pending timer to `delay_ms` milliseconds from now.
+///
+/// Returns `false` if the timer has already run or been cancelled.
+#[allow(dead_code, reason = "no driver uses kernel timers yet")]
+pub fn mod_timer(timer: TimerId, delay_ms: u64) -> bool {
+    without_interrupts(|| {
+        let mut wheel = WHEELS[timer.cpu_id as usize].lock();
+        let expires = wheel.now + ms_to_ticks(delay_ms);
+
+        wheel.modify(timer.id, expires)
+    })
+}
+
+/// Runs expired timers of the current CPU, must be called from the timer interrupt after
+/// end-of-interrupt.
+pub(crate) fn run_expired() {
+    let cpu_id = cpu::current_id();
+
+    if WHEELS.get(cpu_id as usize).is_none() {
+        return;
+    }
+
+    unsafe {
+        if RUNNING {
+            return;
+        }
+
+        RUNNING = true;
+    }
+
+    interrupts::enable();
+
+    loop {
+        let expired = without_interrupts(|| expire(cpu_id, lapic_timer::ticks(cpu_id)));
+
+        if expired.is_empty() {
+            break;
+        }
+
+        for timer in expired {
+            (timer.callback)();
+        }
+    }
+
+    interrupts::disable();
+
+    unsafe { RUNNING = false };
+}
+
+/// Advances the wheel of the CPU up to `ticks`, returns its expired timers.
+fn expire(cpu_id: u64, ticks: u64) -> Vec<Timer> {
+    WHEELS
+        .get(cpu_id as usize)
+        .map_or_else(Vec::new, |wheel| wheel.lock().advance(ticks))
+}
+
+/// Rounds up to timer ticks, at least one.
+fn ms_to_ticks(ms: u64) -> u64 {
+    ms.saturating_mul(KERNEL_TIMER_HZ).div_ceil(1000).max(1)
+}
+
+struct Timer {
+    id: u64,
+    // Tick of expiry.
+    expires: u64,
+    callback: Callback,
+}
+
+struct Wheel {
+    // Last processed tick.
+    now: u64,
+    slots: [[Vec<Timer>; SLOTS]; LEVELS],
+    // Level and slot of pending timers by id.
+    pending: BTreeMap<u64, (usize, usize)>,
+}
+
+impl Wheel {
+    const fn new(now: u64) -> Self {
+        Self {
+            now,
+            slots: [const { [const { Vec::new() }; SLOTS] }; LEVELS],
+            pending: BTreeMap::new(),
+        }
+    }
+
+    fn insert(&mut self, mut timer: Timer) {
+        let delta = timer.expires.saturating_sub(self.now).min(MAX_DELAY);
+        timer.expires = self.now + delta;
+
+        let level = (1..LEVELS)
+            .find(|level| delta >> (LEVEL_BITS * *level as u64) == 0)
+            .map_or(LEVELS - 1, |level| level - 1);
+
+        #[allow(clippy::cast_possible_truncation)]
+        let slot = ((timer.expires >> (LEVEL_BITS * level as u64)) & SLOT_MASK) as usize;
+
+        self.pending.insert(timer.id, (level, slot));
+        self.slots[level][slot].push(timer);
+    }
+
+    fn remove(&mut self, id: u64) -> Option<Timer> {
+        let (level, slot) = self.pending.remove(&id)?;
+        let timers = &mut self.slots[level][slot];
+        let index = timers.iter().position(|timer| timer.id == id)?;
+
+        Some(timers.swap_remove(index))
+    }
+
+    fn modify(&mut self, id: u64, expires: u64) -> bool {
+        let Some(mut timer) = self.remove(id) else {
+            return false;
+        };
+
+        timer.expires = expires;
+        self.insert(timer);
+
+        true
+    }
+
+    /// Processes ticks up to `ticks`, returns expired timers.
+    fn advance(&mut self, ticks: u64) -> Vec<Timer> {
+        let mut expired = Vec::new();
+
+        while self.now < ticks {
+            self.now += 1;
+
+            // Timers of the next slot of every wrapped level move down.
+            for level in 1..LEVELS {
+                let shift = LEVEL_BITS * level as u64;
+
+                if self.now & ((1 << shift) - 1) != 0 {
+                    break;
+                }
+
+                #[allow(clippy::cast_possible_truncation)]
+                let slot = ((self.now >> shift) & SLOT_MASK) as usize;
+
+                for timer in mem::take(&mut self.slots[level][slot]) {
+                    self.insert(timer);
+                }
+            }
+
+            #[allow(clippy::cast_possible_truncation)]
+            let slot = (self.now & SLOT_MASK) as usize;
+
+            for timer in mem::take(&mut self.slots[0][slot]) {
+                self.pending.remove(&timer.id);
+                expired.push(timer);
+            }
+        }
+
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timer(id: u64, expires: u64) -> Timer {
+        Timer {
+            id,
+            expires,
+            callback: Box::new(|| ()),
+        }
+    }
+
+    fn expiry_ticks(wheel: &mut Wheel, until: u64) -> Vec<(u64, u64)> {
+        let mut result = Vec::new();
+
+        while wheel.now < until {
+            let tick = wheel.now + 1;
+
+            for timer in wheel.advance(tick) {
+                result.push((timer.id, tick));
+            }
+        }
+
+        result
+    }
+
+    #[test]
+    fn timers_expire_on_time_at_all_levels() {
+        let mut wheel = Wheel::new(5);
+        let expiries = [6, 69, 70, 133, 4101, 4166, 262_149, 300_000];
+
+        for (id, expires) in expiries.iter().enumerate() {
+            wheel.insert(timer(id as u64, *expires));
+        }
+
+        let result = expiry_ticks(&mut wheel, 300_001);
+        let expected: Vec<_> = expiries
+            .iter()
+            .enumerate()
+            .map(|(id, expires)| (id as u64, *expires))
+            .collect();
+
+        assert_eq!(result, expected);
+        assert!(wheel.pending.is_empty());
+    }
+
+    #[test]
+    fn advance_collects_missed_ticks() {
+        let mut wheel = Wheel::new(0);
+        wheel.insert(timer(1, 10));
+        wheel.insert(timer(2, 100));
+        wheel.insert(timer(3, 1000));
+
+        let ids: Vec<_> = wheel.advance(100).iter().map(|timer| timer.id).collect();
+
+        assert_eq!(ids, [1, 2]);
+        assert_eq!(wheel.pending.len(), 1);
+    }
+
+    #[test]
+    fn remove_and_modify() {
+        let mut wheel = Wheel::new(0);
+        wheel.insert(timer(1, 10));
+        wheel.insert(timer(2, 20));
+
+        assert!(wheel.remove(1).is_some());
+        assert!(wheel.remove(1).is_none());
+
+        assert!(wheel.modify(2, 5000));
+        assert!(!wheel.modify(3, 5000));
+
+        assert!(expiry_ticks(&mut wheel, 4999).is_empty());
+        assert_eq!(expiry_ticks(&mut wheel, 5000), [(2, 5000)]);
+    }
+
+    #[test]
+    fn ap_wheels_advance() {
+        let cpu_id = KERNEL_MAX_CPUS as u64 - 1;
+        WHEELS[cpu_id as usize].lock().insert(timer(1, 3));
+
+        assert!(expire(cpu_id, 2).is_empty());
+        assert!(expire(0, 3).is_empty());
+        assert_eq!(expire(cpu_id, 3).len(), 1);
+        assert!(WHEELS[cpu_id as usize].lock().pending.is_empty());
+    }
+
+    #[test]
+    fn long_delays_are_clamped() {
+        let mut wheel = Wheel::new(0);
+        wheel.insert(timer(1, u64::MAX));
+
+        assert!(wheel.advance(MAX_DELAY - 1).is_empty());
+        assert_eq!(wheel.advance(MAX_DELAY).len(), 1);
+    }
+}